@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::iter::Sum;
+use std::ops::Add;
+use crate::metrics::{Collector, Memory, MetricNode, MetricTotals, COLLECTORS};
+use crate::prefix_map::PrefixMap;
+use crate::results::{Datum, Results};
+use crate::results2::{NodeColumns, SortOrder};
+use crate::snapshot::{KeySample, KeyspaceSample, Snapshot};
+
+/// Totals of sampled keys under a node of the prefix tree.
+#[derive(Debug, Clone, Default)]
+pub struct KeyspaceTreeNodeInfo {
+    /// Number of the keys
+    pub count: u64,
+    /// Totals of metrics of the keys by their collectors, in the order their columns are rendered
+    pub metrics: Vec<(&'static dyn Collector, MetricTotals)>,
+    /// Number of keys whose names are not valid UTF-8
+    pub non_utf8: u64,
+    /// Sum of lengths of key names
+    pub key_length: u64,
+}
+impl Add for KeyspaceTreeNodeInfo {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        let mut metrics = self.metrics;
+        for (collector, other) in other.metrics {
+            match metrics.iter_mut().find(|(c, _)| c.name() == collector.name()) {
+                Some((_, totals)) => *totals = *totals + other,
+                None => metrics.push((collector, other)),
+            }
+        }
+        Self {
+            count: self.count + other.count,
+            metrics,
+            non_utf8: self.non_utf8 + other.non_utf8,
+            key_length: self.key_length + other.key_length,
+        }
+    }
+}
+
+impl KeyspaceTreeNodeInfo {
+    /// Totals of a single key with metrics of given collectors.
+    pub fn from_key(key: &KeySample, collectors: &[&'static dyn Collector]) -> Self {
+        KeyspaceTreeNodeInfo {
+            count: 1,
+            metrics: collectors.iter().map(|collector| (*collector, collector.key_totals(key).unwrap_or_default())).collect(),
+            non_utf8: if std::str::from_utf8(&key.key).is_ok() { 0 } else { 1 },
+            key_length: key.key.len() as u64,
+        }
+    }
+
+    /// Sum of memory usage of the keys in bytes.
+    pub fn memory_usage(&self) -> f64 {
+        self.metrics.iter().find(|(collector, _)| collector.name() == Memory.name()).map_or(0.0, |(_, totals)| totals.sum)
+    }
+
+    /// Names of the columns of nodes with these totals, the columns of metrics are contributed by their collectors.
+    pub fn column_names(&self, options: &ResultsOptions) -> Vec<String> {
+        let mut columns = ["count", "count_percent", "estimated_total_count"].map(str::to_string).to_vec();
+        for (collector, _) in &self.metrics {
+            columns.extend(collector.columns().iter().map(|column| column.to_string()));
+        }
+        columns.extend(["avg_key_length", "estimated_total_key_length"].map(str::to_string));
+        if options.shorten_prefixes_to.is_some() {
+            columns.push("estimated_prefix_savings".to_string());
+        }
+        if self.non_utf8 > 0 {
+            columns.push("non_utf8_count".to_string());
+        }
+        columns
+    }
+}
+
+/// Collectors with values for some of the keys, the `selected` ones first in their order, others in the order of `COLLECTORS`.
+fn collectors<'k, I: Iterator<Item = &'k KeySample> + Clone>(keys: I, selected: &[&'static dyn Collector]) -> Vec<&'static dyn Collector> {
+    let mut collectors = Vec::<&'static dyn Collector>::new();
+    for collector in selected.iter().chain(COLLECTORS.iter()) {
+        if !collectors.iter().any(|c| c.name() == collector.name()) && keys.clone().any(|key| collector.key_totals(key).is_some()) {
+            collectors.push(*collector);
+        }
+    }
+    collectors
+}
+
+impl Sum for KeyspaceTreeNodeInfo {
+    fn sum<I>(iter: I) -> Self
+    where
+        I: Iterator<Item = Self>,
+    {
+        iter.fold(Default::default(), |acc, x| acc + x)
+    }
+}
+
+/// Totals of sampled keys under a node with estimates for all keys of the keyspace.
+#[derive(Debug, Clone)]
+pub struct ExtendedKeyspaceTreeNodeInfo {
+    /// Totals of the sampled keys
+    pub info: KeyspaceTreeNodeInfo,
+    /// Estimated number of keys under the node, the sampled ones scaled to the whole keyspace
+    pub estimated_total_count: f64,
+    /// Estimated memory usage of the keys in bytes
+    pub estimated_total_memory_usage: f64,
+    /// Bytes saved if the prefix of this node was shortened, only set for prefixes and keys
+    pub estimated_prefix_savings: Option<f64>,
+}
+impl Default for ExtendedKeyspaceTreeNodeInfo {
+    fn default() -> Self {
+        ExtendedKeyspaceTreeNodeInfo {
+            info: Default::default(),
+            estimated_total_count: 0.0,
+            estimated_total_memory_usage: 0.0,
+            estimated_prefix_savings: None,
+        }
+    }
+}
+impl Add for ExtendedKeyspaceTreeNodeInfo {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            info: self.info + other.info,
+            estimated_total_count: self.estimated_total_count + other.estimated_total_count,
+            estimated_total_memory_usage: self.estimated_total_memory_usage + other.estimated_total_memory_usage,
+            estimated_prefix_savings: None,
+        }
+    }
+}
+
+impl Sum for ExtendedKeyspaceTreeNodeInfo {
+    fn sum<I>(iter: I) -> Self
+        where
+            I: Iterator<Item = Self>,
+    {
+        iter.fold(Default::default(), |acc, x| acc + x)
+    }
+}
+
+/// Columns of nodes of [`keyspace_tree`]s.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyspaceColumns;
+
+impl NodeColumns<ExtendedKeyspaceTreeNodeInfo> for KeyspaceColumns {
+    fn columns(&self, info: &ExtendedKeyspaceTreeNodeInfo, parent: &ExtendedKeyspaceTreeNodeInfo) -> HashMap<String, Datum> {
+        let mut map = HashMap::new();
+        map.insert("count".to_string(), Datum::Count(info.info.count as i64));
+        map.insert("count_percent".to_string(), Datum::Percent(info.info.count as f64 / parent.info.count as f64));
+        map.insert("estimated_total_count".to_string(), Datum::Stat(info.estimated_total_count));
+        for (collector, totals) in &info.info.metrics {
+            if totals.known > 0 {
+                let parent = parent.info.metrics.iter()
+                    .find(|(c, _)| c.name() == collector.name())
+                    .map_or(totals, |(_, parent)| parent);
+                let scale = info.estimated_total_count / info.info.count as f64;
+                for (column, datum) in collector.values(MetricNode { totals, parent, scale }) {
+                    map.insert(column.to_string(), datum);
+                }
+            }
+        }
+        map.insert("avg_key_length".to_string(), Datum::Stat(info.info.key_length as f64 / info.info.count as f64));
+        map.insert(
+            "estimated_total_key_length".to_string(),
+            Datum::Stat(info.info.key_length as f64 * info.estimated_total_count / info.info.count as f64),
+        );
+        if let Some(savings) = info.estimated_prefix_savings {
+            map.insert("estimated_prefix_savings".to_string(), Datum::Stat(savings));
+        }
+        map.insert("non_utf8_count".to_string(), Datum::Count(info.info.non_utf8 as i64));
+        map
+    }
+
+    fn keys(&self, info: &ExtendedKeyspaceTreeNodeInfo) -> u64 {
+        info.info.count
+    }
+}
+
+/// Column used for charts of the results.
+pub const CHART_COLUMN: &str = "estimated_total_memory_usage";
+
+/// Column used for charts and sorting of hot keys.
+pub const FREQUENCY_COLUMN: &str = "frequency";
+
+/// Controls which parts of the tree end up in the results.
+#[derive(Debug, Clone)]
+pub struct ResultsOptions {
+    /// Children of a prefix are listed only if it has at least this many sampled keys.
+    pub min_count: u64,
+    /// Maximum depth of listed prefixes, keyspaces are at depth 1.
+    pub max_depth: Option<usize>,
+    /// Estimate how much memory would be saved if names of prefixes were shortened to this many bytes.
+    pub shorten_prefixes_to: Option<usize>,
+    /// Metrics whose columns come first, in this order. Columns of other metrics with values follow them.
+    pub metrics: Vec<&'static dyn Collector>,
+}
+
+impl Default for ResultsOptions {
+    fn default() -> Self {
+        ResultsOptions {
+            min_count: 3,
+            max_depth: None,
+            shorten_prefixes_to: None,
+            metrics: Vec::new(),
+        }
+    }
+}
+
+/// Prefix tree of sampled keys of a keyspace with totals and estimates of every node.
+/// Prefixes with a single child and no key of their own are merged into the child.
+pub fn keyspace_tree(sample: &KeyspaceSample, options: &ResultsOptions) -> PrefixMap<ExtendedKeyspaceTreeNodeInfo> {
+    let analyzed_share = sample.analyzed_share();
+    let collectors = collectors(sample.keys.iter(), &options.metrics);
+    sample.prefix_map().simplify().transform_to_prefix_map::<ExtendedKeyspaceTreeNodeInfo, _>(&|_key, value, mut children| {
+        let mut out_value = children.values().map(|map| map.value.clone()).sum::<ExtendedKeyspaceTreeNodeInfo>();
+        if let Some(key) = value {
+            out_value.info = out_value.info + KeyspaceTreeNodeInfo::from_key(key, &collectors);
+        }
+        // Every key under a prefix starts with it, so each of them would be shorter by the same number of bytes
+        if let Some(length) = options.shorten_prefixes_to {
+            for (prefix, child) in children.iter_mut() {
+                let saved_bytes = prefix.len().saturating_sub(length) as f64;
+                child.value.estimated_prefix_savings = Some(saved_bytes * child.value.estimated_total_count);
+            }
+        }
+        (
+            ExtendedKeyspaceTreeNodeInfo {
+                estimated_total_memory_usage: out_value.info.memory_usage() / analyzed_share,
+                estimated_total_count: out_value.info.count as f64 / analyzed_share,
+                info: out_value.info,
+                estimated_prefix_savings: None,
+            },
+            children,
+        )
+    })
+}
+
+/// Builds per-prefix results of all sampled keyspaces of the snapshot, items are sorted by estimated memory usage.
+pub fn build_results(snapshot: &Snapshot, options: &ResultsOptions) -> Results {
+    // Keyspaces are summed in their order, so the columns of metrics are in the same order for every snapshot
+    let trees = snapshot.samples.iter()
+        .map(|sample| (sample.keyspace.to_string().into_bytes(), keyspace_tree(sample, options)))
+        .collect::<Vec<_>>();
+    let merged = PrefixMap::new(trees.iter().map(|(_, tree)| tree.value.clone()).sum::<ExtendedKeyspaceTreeNodeInfo>(), trees.into_iter().collect());
+
+    // Columns of per-key attributes are shown only if some keys have them
+    let columns = merged.value.info.column_names(options);
+    Results::from_prefix_map(&merged, &KeyspaceColumns, columns, SortOrder::descending(CHART_COLUMN), options)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+    use crate::metrics::Ttl;
+    use crate::results::{write_text, Datum, Results};
+    use crate::results2::{sorted, PrefixNode, SortOrder};
+    use crate::snapshot::{KeySample, KeyspaceSample, Snapshot};
+    use super::{build_results, keyspace_tree, KeyspaceColumns, ResultsOptions, FREQUENCY_COLUMN};
+
+    fn snapshot() -> Snapshot {
+        let info = KeyspaceInfo::new(8, 0, 0);
+        let mut snapshot = Snapshot::new(
+            "localhost".to_string(),
+            6379,
+            BTreeMap::new(),
+            KeyspacesInfo { keyspaces: HashMap::from([(KeyspaceId::new(0), info.clone())]) },
+        );
+        snapshot.samples.push(KeyspaceSample {
+            keyspace: KeyspaceId::new(0),
+            info,
+            sample_size: 4,
+            keys: [("foo:1", 10), ("foo:2", 20), ("foo:3", 30), ("bar", 40)]
+                .iter()
+                .map(|(key, memory_usage)| KeySample::new(key.as_bytes().to_vec(), *memory_usage))
+                .collect(),
+            skipped: Default::default(),
+            matching_keys: None,
+        });
+        snapshot
+    }
+
+    #[test]
+    fn test_estimates() {
+        let results = build_results(&snapshot(), &ResultsOptions::default());
+        let keyspace = &results.items[0];
+        assert_eq!(keyspace.name, "0");
+        assert!(matches!(keyspace.columns["estimated_total_count"], Datum::Stat(c) if c == 8.0));
+        assert!(matches!(keyspace.columns["estimated_total_memory_usage"], Datum::Stat(m) if m == 200.0));
+
+        let foo = keyspace.children.iter().find(|item| item.name == "foo:").unwrap();
+        assert!(matches!(foo.columns["count"], Datum::Count(3)));
+        assert_eq!(foo.children.len(), 3);
+    }
+
+    #[test]
+    fn test_matching_keys() {
+        let mut snapshot = snapshot();
+        // Only 6 of 8 keys match the pattern the sample was restricted to
+        snapshot.samples[0].matching_keys = Some(6.0);
+        let results = build_results(&snapshot, &ResultsOptions::default());
+        assert!(matches!(results.items[0].columns["estimated_total_count"], Datum::Stat(c) if c == 6.0));
+        assert!(matches!(results.items[0].columns["estimated_total_memory_usage"], Datum::Stat(m) if m == 150.0));
+    }
+
+    #[test]
+    fn test_frequency() {
+        let mut snapshot = snapshot();
+        for (key, frequency) in snapshot.samples[0].keys.iter_mut().zip([5, 1, 9, 2]) {
+            key.frequency = Some(frequency);
+        }
+        let mut results = build_results(&snapshot, &ResultsOptions::default());
+        results.sort_by_column(FREQUENCY_COLUMN);
+        let foo = &results.items[0].children[0];
+        assert_eq!(foo.name, "foo:");
+        assert!(matches!(foo.columns["frequency"], Datum::Count(15)));
+        assert!(matches!(foo.columns["max_frequency"], Datum::Count(9)));
+        assert_eq!(foo.children[0].name, "foo:3");
+    }
+
+    #[test]
+    fn test_metrics_order() {
+        let mut snapshot = snapshot();
+        for (key, ttl) in snapshot.samples[0].keys.iter_mut().zip([-1, 100, -1, -1]) {
+            key.ttl = Some(ttl);
+        }
+        let position = |results: &Results, column: &str| results.columns.iter().position(|c| c == column).unwrap();
+        let results = build_results(&snapshot, &ResultsOptions::default());
+        assert!(position(&results, "memory_usage") < position(&results, "ttl_percent"));
+        let results = build_results(&snapshot, &ResultsOptions { metrics: vec![&Ttl], ..Default::default() });
+        assert!(position(&results, "ttl_percent") < position(&results, "memory_usage"));
+        assert!(!results.columns.contains(&"type".to_string()));
+        let foo = results.items[0].children.iter().find(|item| item.name == "foo:").unwrap();
+        assert!(matches!(foo.columns["ttl_percent"], Datum::Percent(p) if (p - 1.0 / 3.0).abs() < 1e-9));
+        assert!(matches!(foo.columns["memory_usage"], Datum::Count(60)));
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        let mut snapshot = snapshot();
+        snapshot.samples[0].keys[0].key = b"foo:\xff".to_vec();
+        let results = build_results(&snapshot, &ResultsOptions::default());
+        assert!(results.columns.contains(&"non_utf8_count".to_string()));
+        let foo = results.items[0].children.iter().find(|item| item.name == "foo:").unwrap();
+        assert!(matches!(foo.columns["non_utf8_count"], Datum::Count(1)));
+        assert!(foo.children.iter().any(|item| item.name == "foo:\\xff"));
+    }
+
+    #[test]
+    fn test_keyspace_tree_rendering() {
+        let snapshot = snapshot();
+        let tree = keyspace_tree(&snapshot.samples[0], &ResultsOptions::default());
+        let columns = ["count", "count_percent", "memory_usage"].map(str::to_string);
+        let mut text = String::new();
+        write_text(sorted(PrefixNode::roots(&tree, &KeyspaceColumns), SortOrder::descending("memory_usage")), &columns, &mut text).unwrap();
+        assert_eq!(
+            text,
+            "        | count | count_percent | memory_usage\n\
+             foo:    |     3 |         75.0% |           60\n\
+            \x20 foo:3 |     1 |         33.3% |           30\n\
+            \x20 foo:2 |     1 |         33.3% |           20\n\
+            \x20 foo:1 |     1 |         33.3% |           10\n\
+             bar     |     1 |         25.0% |           40\n",
+        );
+    }
+
+    #[test]
+    fn test_key_length() {
+        let options = ResultsOptions { shorten_prefixes_to: Some(1), ..Default::default() };
+        let results = build_results(&snapshot(), &options);
+        let keyspace = &results.items[0];
+        assert!(!keyspace.columns.contains_key("estimated_prefix_savings"));
+        assert!(matches!(keyspace.columns["estimated_total_key_length"], Datum::Stat(l) if l == 36.0));
+        let foo = keyspace.children.iter().find(|item| item.name == "foo:").unwrap();
+        assert!(matches!(foo.columns["avg_key_length"], Datum::Stat(l) if l == 5.0));
+        // "foo:" shortened to 1 byte saves 3 bytes in each of 6 estimated keys
+        assert!(matches!(foo.columns["estimated_prefix_savings"], Datum::Stat(s) if s == 18.0));
+    }
+
+    #[test]
+    fn test_min_count() {
+        let results = build_results(&snapshot(), &ResultsOptions { min_count: 4, ..Default::default() });
+        let foo = results.items[0].children.iter().find(|item| item.name == "foo:").unwrap();
+        assert!(foo.children.is_empty());
+    }
+}
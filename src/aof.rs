@@ -0,0 +1,756 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::analysis::ResultsOptions;
+use crate::keyspace_info::KeyspaceId;
+use crate::prefix_map::{escape_key, PrefixMap};
+use crate::progress::Progress;
+use crate::rdb::RdbParser;
+use crate::results::{Datum, Results};
+use crate::results2::{NodeColumns, SortOrder};
+use crate::run_info::{RunInfo, SampleInfo};
+use crate::tui::SampledKeys;
+
+/// Column used for charts of the results.
+pub const CHART_COLUMN: &str = "size";
+
+/// Size of numbers stored by `INCR` and similar commands.
+const NUMBER_SIZE: u64 = 8;
+/// Size of a score of a sorted set.
+const SCORE_SIZE: u64 = 8;
+
+/// State of a key after replaying the commands.
+#[derive(Debug, Clone, Default)]
+pub struct AofKey {
+    pub value_type: Option<&'static str>,
+    /// Approximate size of the data in bytes
+    pub size: u64,
+    pub elements: u64,
+    /// Number of commands which modified the key
+    pub writes: u64,
+    /// Whether the key exists at the end of the file
+    pub exists: bool,
+    /// Members of hashes, sets and sorted sets added by commands with their sizes,
+    /// members loaded from an RDB preamble are included only in `size` and `elements`
+    members: HashMap<Vec<u8>, u64>,
+}
+
+impl AofKey {
+    fn clear(&mut self) {
+        self.exists = false;
+        self.size = 0;
+        self.elements = 0;
+        self.members.clear();
+    }
+
+    /// Adds or replaces a member of a hash, set or sorted set.
+    fn set_member(&mut self, member: &[u8], size: u64) {
+        match self.members.insert(member.to_vec(), size) {
+            Some(old_size) => self.size -= old_size,
+            None => self.elements += 1,
+        }
+        self.size += size;
+    }
+
+    fn remove_member(&mut self, member: &[u8]) {
+        match self.members.remove(member) {
+            Some(size) => {
+                self.size -= size;
+                self.elements -= 1;
+            },
+            // The member may be one of those loaded from the RDB preamble
+            None if self.elements as usize > self.members.len() => self.remove_elements(1),
+            None => {},
+        }
+    }
+
+    /// Removes elements which are not known first, their size is estimated from the average size.
+    /// When all of them are gone, known members are removed, which ones is not known (e.g. for `ZPOPMIN`).
+    fn remove_elements(&mut self, count: u64) {
+        let tracked_size = self.members.values().sum::<u64>();
+        let untracked = self.elements - self.members.len() as u64;
+        let removed = count.min(untracked);
+        if removed > 0 {
+            self.size -= (self.size - tracked_size) * removed / untracked;
+            self.elements -= removed;
+        }
+        let mut members = self.members.keys().cloned().collect::<Vec<_>>();
+        members.sort();
+        for member in members.into_iter().take((count - removed) as usize) {
+            self.remove_member(&member);
+        }
+    }
+}
+
+/// Keys and statistics reconstructed from an AOF.
+#[derive(Debug, Default)]
+pub struct AofAnalysis {
+    pub keyspaces: HashMap<KeyspaceId, HashMap<Vec<u8>, AofKey>>,
+    /// Number of replayed commands
+    pub commands: u64,
+    /// Unix times in seconds of the first and the last timestamp annotation (`aof-timestamp-enabled`)
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    /// Whether the last command was incomplete
+    pub truncated: bool,
+    db: KeyspaceId,
+}
+
+fn invalid_data<T>(message: String) -> std::io::Result<T> {
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message))
+}
+
+/// Reads an AOF. The path can be a single AOF (optionally with an RDB preamble), a manifest of
+/// a multi-part AOF (Redis 7) or a directory with the manifest.
+pub fn read_aof(path: &Path, progress: &dyn Progress) -> std::io::Result<AofAnalysis> {
+    let mut analysis = AofAnalysis::default();
+    for file in aof_files(path)? {
+        progress.message(&format!("Reading {}", file.display()));
+        analysis.read_file(&file)?;
+    }
+    Ok(analysis)
+}
+
+/// Files to read in the order of replaying.
+fn aof_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let manifest = if path.is_dir() {
+        let mut manifests = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|extension| extension == "manifest"))
+            .collect::<Vec<_>>();
+        match manifests.len() {
+            1 => manifests.remove(0),
+            0 => return invalid_data(format!("no AOF manifest in {}", path.display())),
+            _ => return invalid_data(format!("multiple AOF manifests in {}", path.display())),
+        }
+    } else if path.extension().is_some_and(|extension| extension == "manifest") {
+        path.to_path_buf()
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let directory = manifest.parent().unwrap_or_else(|| Path::new("."));
+    parse_manifest(&std::fs::read_to_string(&manifest)?)
+        .map(|files| files.into_iter().map(|file| directory.join(file)).collect())
+}
+
+/// Returns files of a manifest, the base file first and then incremental files. History files
+/// are already replaced by the base file and skipped.
+fn parse_manifest(manifest: &str) -> std::io::Result<Vec<String>> {
+    let mut base = None;
+    let mut incremental = Vec::new();
+    for line in manifest.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let tokens = split_arguments(line)?;
+        let value = |name: &str| tokens.chunks(2)
+            .find(|pair| pair[0] == name)
+            .and_then(|pair| pair.get(1))
+            .cloned();
+        let file = match value("file") {
+            Some(file) => file,
+            None => return invalid_data(format!("invalid manifest line: {}", line)),
+        };
+        match value("type").as_deref() {
+            Some("b") => base = Some(file),
+            Some("i") => incremental.push(file),
+            Some("h") => {},
+            _ => return invalid_data(format!("invalid manifest line: {}", line)),
+        }
+    }
+    Ok(base.into_iter().chain(incremental).collect())
+}
+
+/// Splits a line into arguments, quoted arguments can contain spaces and escapes.
+fn split_arguments(line: &str) -> std::io::Result<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let quote = match chars.peek() {
+            None => return Ok(arguments),
+            Some(&c) if c == '"' || c == '\'' => chars.next(),
+            Some(_) => None,
+        };
+        let mut argument = String::new();
+        loop {
+            match (chars.next(), quote) {
+                (None, Some(_)) => return invalid_data(format!("unbalanced quotes: {}", line)),
+                (None, None) => break,
+                (Some(c), Some(q)) if c == q => break,
+                (Some('\\'), Some('"')) => match chars.next() {
+                    Some('n') => argument.push('\n'),
+                    Some('t') => argument.push('\t'),
+                    Some(c) => argument.push(c),
+                    None => return invalid_data(format!("unbalanced quotes: {}", line)),
+                },
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), _) => argument.push(c),
+            }
+        }
+        arguments.push(argument);
+    }
+}
+
+impl AofAnalysis {
+    fn read_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if reader.fill_buf()?.starts_with(b"REDIS") {
+            self.read_rdb(&mut reader)?;
+        }
+        self.read_commands(&mut reader)
+    }
+
+    /// Loads keys from an RDB file or an RDB preamble.
+    fn read_rdb<R: Read>(&mut self, reader: &mut R) -> std::io::Result<()> {
+        let mut parser = RdbParser::new(&mut *reader)?;
+        while let Some(entry) = parser.next_entry()? {
+            let key = self.keyspaces.entry(entry.db).or_default().entry(entry.key).or_default();
+            key.clear();
+            key.exists = true;
+            key.value_type = Some(entry.value_type);
+            key.size = entry.layout.data_size() as u64;
+            key.elements = entry.elements;
+        }
+        // Checksum
+        reader.read_exact(&mut [0; 8])
+    }
+
+    fn read_commands<R: BufRead>(&mut self, reader: &mut R) -> std::io::Result<()> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            if !line.ends_with(b"\n") {
+                self.truncated = true;
+                return Ok(());
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end();
+            if let Some(annotation) = text.strip_prefix('#') {
+                if let Some(timestamp) = annotation.strip_prefix("TS:").and_then(|ts| ts.parse::<u64>().ok()) {
+                    self.first_timestamp.get_or_insert(timestamp);
+                    self.last_timestamp = Some(timestamp);
+                }
+                continue;
+            }
+            let count = match text.strip_prefix('*').and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) => count,
+                None => return invalid_data(format!("invalid AOF command header: {}", text)),
+            };
+            let mut arguments = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                match read_bulk_string(reader)? {
+                    Some(argument) => arguments.push(argument),
+                    None => {
+                        self.truncated = true;
+                        return Ok(());
+                    },
+                }
+            }
+            if !arguments.is_empty() {
+                self.apply(&arguments);
+            }
+        }
+    }
+
+    fn keys(&mut self) -> &mut HashMap<Vec<u8>, AofKey> {
+        self.keyspaces.entry(self.db).or_default()
+    }
+
+    /// Records a write to a key of given type, the key is created if it does not exist.
+    fn write(&mut self, key: &[u8], value_type: &'static str) -> &mut AofKey {
+        let key = self.keys().entry(key.to_vec()).or_default();
+        if !key.exists {
+            key.clear();
+            key.exists = true;
+            key.value_type = Some(value_type);
+            if value_type == "string" {
+                key.elements = 1;
+            }
+        }
+        key.writes += 1;
+        key
+    }
+
+    /// Records a write to a key only if it exists.
+    fn touch(&mut self, key: &[u8]) -> Option<&mut AofKey> {
+        match self.keys().get_mut(key) {
+            Some(key) if key.exists => {
+                key.writes += 1;
+                Some(key)
+            },
+            _ => None,
+        }
+    }
+
+    fn set_string(&mut self, key: &[u8], size: u64) {
+        let key = self.write(key, "string");
+        key.clear();
+        key.exists = true;
+        key.value_type = Some("string");
+        key.elements = 1;
+        key.size = size;
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        if let Some(key) = self.touch(key) {
+            key.clear();
+        }
+    }
+
+    /// Deletes keys with aggregate values when their last element is removed.
+    fn remove_empty(&mut self, key: &[u8]) {
+        if let Some(key) = self.keys().get_mut(key) {
+            if key.exists && key.elements == 0 && key.value_type != Some("string") {
+                key.clear();
+            }
+        }
+    }
+
+    /// Removes elements from the head or the tail of a list or other elements which are not known.
+    fn pop(&mut self, key: &[u8], count: u64) {
+        if let Some(key) = self.touch(key) {
+            key.remove_elements(count);
+        }
+        self.remove_empty(key);
+    }
+
+    fn add_members<'a, I: Iterator<Item = (&'a [u8], u64)>>(&mut self, key: &[u8], value_type: &'static str, members: I) {
+        let key = self.write(key, value_type);
+        for (member, size) in members {
+            key.set_member(member, size);
+        }
+    }
+
+    fn remove_members(&mut self, key: &[u8], members: &[Vec<u8>]) {
+        if let Some(key) = self.touch(key) {
+            for member in members {
+                key.remove_member(member);
+            }
+        }
+        self.remove_empty(key);
+    }
+
+    fn push(&mut self, key: &[u8], value_type: &'static str, values: &[Vec<u8>]) {
+        let key = self.write(key, value_type);
+        key.elements += values.len() as u64;
+        key.size += values.iter().map(|value| value.len() as u64).sum::<u64>();
+    }
+
+    /// Moves a key to another key, possibly in another keyspace.
+    fn rename(&mut self, from: &[u8], to: &[u8], db: KeyspaceId, copy: bool) {
+        let value = match self.keys().get_mut(from) {
+            Some(key) if key.exists => {
+                key.writes += 1;
+                let value = key.clone();
+                if !copy {
+                    key.clear();
+                }
+                value
+            },
+            _ => return,
+        };
+        let target = self.keyspaces.entry(db).or_default().entry(to.to_vec()).or_default();
+        let writes = target.writes + 1;
+        *target = AofKey { writes, ..value };
+    }
+
+    /// Updates keys according to a command.
+    fn apply(&mut self, arguments: &[Vec<u8>]) {
+        self.commands += 1;
+        let command = String::from_utf8_lossy(&arguments[0]).to_ascii_uppercase();
+        let arg = |index: usize| arguments.get(index).map_or(&[][..], |argument| &argument[..]);
+        let number = |index: usize| std::str::from_utf8(arg(index)).ok().and_then(|n| n.parse::<u64>().ok());
+        let size = |index: usize| arg(index).len() as u64;
+        let key = arg(1);
+        let rest = arguments.get(2..).unwrap_or(&[]);
+        match command.as_str() {
+            "SELECT" => self.db = KeyspaceId::new(number(1).unwrap_or(0) as i64),
+            "FLUSHDB" => self.keys().values_mut().for_each(AofKey::clear),
+            "FLUSHALL" => self.keyspaces.values_mut().flat_map(|keys| keys.values_mut()).for_each(AofKey::clear),
+            "DEL" | "UNLINK" => arguments[1..].iter().for_each(|key| self.delete(key)),
+            "RENAME" | "RENAMENX" => self.rename(key, arg(2), self.db, false),
+            "MOVE" => self.rename(key, key, KeyspaceId::new(number(2).unwrap_or(0) as i64), false),
+            "COPY" => {
+                let db = rest.iter().position(|a| a.eq_ignore_ascii_case(b"DB"))
+                    .and_then(|index| number(index + 3))
+                    .map_or(self.db, |db| KeyspaceId::new(db as i64));
+                self.rename(key, arg(2), db, true);
+            },
+            "SET" | "GETSET" | "SETNX" => self.set_string(key, size(2)),
+            "SETEX" | "PSETEX" => self.set_string(key, size(3)),
+            "MSET" | "MSETNX" => arguments[1..].chunks(2).for_each(|pair| {
+                self.set_string(&pair[0], pair.get(1).map_or(0, |value| value.len() as u64));
+            }),
+            "APPEND" => {
+                let value_size = size(2);
+                self.write(key, "string").size += value_size;
+            },
+            "SETRANGE" => {
+                // The value overwrites bytes from the offset, the string grows only if it ends beyond them
+                let end = number(2).unwrap_or(0) + size(3);
+                let key = self.write(key, "string");
+                key.size = key.size.max(end);
+            },
+            "SETBIT" => {
+                let bytes = number(2).map_or(0, |offset| offset / 8 + 1);
+                let key = self.write(key, "string");
+                key.size = key.size.max(bytes);
+            },
+            "INCR" | "DECR" | "INCRBY" | "DECRBY" | "INCRBYFLOAT" => {
+                let key = self.write(key, "string");
+                key.size = key.size.max(NUMBER_SIZE);
+            },
+            "PFADD" | "PFMERGE" => self.push(key, "string", &[]),
+            "RPUSH" | "LPUSH" | "RPUSHX" | "LPUSHX" => self.push(key, "list", rest),
+            "LINSERT" => self.push(key, "list", &arguments[arguments.len() - 1..]),
+            "LPOP" | "RPOP" => self.pop(key, number(2).unwrap_or(1)),
+            "RPOPLPUSH" | "LMOVE" => {
+                let element_size = match self.keys().get(key) {
+                    Some(source) if source.exists && source.elements > 0 => source.size / source.elements,
+                    _ => return,
+                };
+                self.pop(key, 1);
+                let destination = self.write(arg(2), "list");
+                destination.elements += 1;
+                destination.size += element_size;
+            },
+            "HSET" | "HMSET" | "HSETNX" => self.add_members(key, "hash", rest.chunks(2).map(|pair| {
+                (&pair[0][..], pair.iter().map(|a| a.len() as u64).sum())
+            })),
+            "HINCRBY" | "HINCRBYFLOAT" => self.add_members(key, "hash", std::iter::once((arg(2), size(2) + NUMBER_SIZE))),
+            "HDEL" => self.remove_members(key, rest),
+            "SADD" => self.add_members(key, "set", rest.iter().map(|member| (&member[..], member.len() as u64))),
+            "SREM" => self.remove_members(key, rest),
+            "SMOVE" if self.keys().get(key).is_some_and(|source| source.exists) => {
+                self.remove_members(key, arguments.get(3..).unwrap_or_default());
+                self.add_members(arg(2), "set", std::iter::once((arg(3), size(3))));
+            },
+            "ZADD" | "GEOADD" => {
+                // Options are followed by (longitude latitude) score member pairs
+                let fields = if command == "ZADD" { 2 } else { 3 };
+                let start = rest.iter()
+                    .position(|a| std::str::from_utf8(a).is_ok_and(|a| a.parse::<f64>().is_ok()))
+                    .unwrap_or(rest.len());
+                self.add_members(key, "zset", rest[start..].chunks(fields).filter(|chunk| chunk.len() == fields).map(|chunk| {
+                    (&chunk[fields - 1][..], chunk[fields - 1].len() as u64 + SCORE_SIZE)
+                }));
+            },
+            "ZINCRBY" => self.add_members(key, "zset", std::iter::once((arg(3), size(3) + SCORE_SIZE))),
+            "ZREM" => self.remove_members(key, rest),
+            "ZPOPMIN" | "ZPOPMAX" => self.pop(key, number(2).unwrap_or(1)),
+            "XADD" => self.push(key, "stream", &arguments[arguments.len().min(3)..]),
+            "XDEL" => self.pop(key, rest.len() as u64),
+            // Commands which change keys in a way which cannot be followed without the values
+            _ if arguments.len() > 1 => { self.touch(key); },
+            _ => {},
+        }
+    }
+
+    fn sorted_keyspaces(&self) -> Vec<(&KeyspaceId, &HashMap<Vec<u8>, AofKey>)> {
+        let mut keyspaces = self.keyspaces.iter().collect::<Vec<_>>();
+        keyspaces.sort_by_key(|(keyspace, _)| keyspace.as_i64());
+        keyspaces
+    }
+
+    /// Time covered by the timestamp annotations.
+    fn duration(&self) -> Option<u64> {
+        match (self.first_timestamp, self.last_timestamp) {
+            (Some(first), Some(last)) if last > first => Some(last - first),
+            _ => None,
+        }
+    }
+
+    pub fn run_info(&self, path: &Path) -> RunInfo {
+        RunInfo {
+            host: path.display().to_string(),
+            port: 0,
+            started_at: self.last_timestamp.map_or_else(SystemTime::now, |ts| UNIX_EPOCH + Duration::from_secs(ts)),
+            samples: self.sorted_keyspaces().into_iter().map(|(keyspace, keys)| {
+                let total_keys = keys.values().filter(|key| key.exists).count() as u64;
+                SampleInfo { keyspace: *keyspace, sample_size: total_keys, total_keys, skipped: 0 }
+            }).collect(),
+        }
+    }
+
+    /// Existing keys with their sizes.
+    pub fn sampled_keys(&self) -> SampledKeys {
+        self.keyspaces.iter().map(|(keyspace, keys)| {
+            (
+                keyspace.to_string(),
+                keys.iter()
+                    .filter(|(_, key)| key.exists)
+                    .map(|(name, key)| (escape_key(name), key.size))
+                    .collect(),
+            )
+        }).collect()
+    }
+}
+
+fn read_bulk_string<R: BufRead>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header)?;
+    if !header.ends_with(b"\n") {
+        return Ok(None);
+    }
+    let text = String::from_utf8_lossy(&header);
+    let length = match text.trim_end().strip_prefix('$').and_then(|length| length.parse::<usize>().ok()) {
+        Some(length) => length,
+        None => return invalid_data(format!("invalid AOF argument header: {}", text.trim_end())),
+    };
+    // The length comes from the file, the buffer grows only with really read data
+    let with_terminator = match length.checked_add(2) {
+        Some(with_terminator) => with_terminator,
+        None => return invalid_data(format!("invalid AOF argument length: {}", length)),
+    };
+    let mut value = Vec::new();
+    reader.by_ref().take(with_terminator as u64).read_to_end(&mut value)?;
+    if value.len() < with_terminator {
+        // The file ends in the middle of the argument
+        return Ok(None);
+    }
+    if !value.ends_with(b"\r\n") {
+        return invalid_data(format!("AOF argument of {} bytes is not terminated", length));
+    }
+    value.truncate(length);
+    Ok(Some(value))
+}
+
+/// Keys under a prefix, deleted keys are included in writes only.
+#[derive(Debug, Clone, Copy, Default)]
+struct AofNodeInfo {
+    /// All keys seen in the file
+    keys: u64,
+    count: u64,
+    size: u64,
+    writes: u64,
+    value_type: Option<&'static str>,
+}
+
+impl AofNodeInfo {
+    fn from_key(key: &AofKey) -> Self {
+        AofNodeInfo {
+            keys: 1,
+            count: if key.exists { 1 } else { 0 },
+            size: key.size,
+            writes: key.writes,
+            value_type: if key.exists { key.value_type } else { None },
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        AofNodeInfo {
+            keys: self.keys + other.keys,
+            count: self.count + other.count,
+            size: self.size + other.size,
+            writes: self.writes + other.writes,
+            value_type: match (self.value_type, other.value_type) {
+                (None, value_type) | (value_type, None) => value_type,
+                (Some(l), Some(r)) if l == r => Some(l),
+                _ => Some("mixed"),
+            },
+        }
+    }
+}
+
+fn share(part: u64, total: u64) -> Datum {
+    Datum::Percent(if total > 0 { part as f64 / total as f64 } else { 0.0 })
+}
+
+/// Columns of nodes of the tree of keys of an AOF covering `duration` seconds.
+struct AofColumns {
+    duration: Option<u64>,
+}
+
+impl NodeColumns<AofNodeInfo> for AofColumns {
+    fn columns(&self, info: &AofNodeInfo, total: &AofNodeInfo) -> HashMap<String, Datum> {
+        let mut map = HashMap::new();
+        map.insert("count".to_string(), Datum::Count(info.count as i64));
+        map.insert("count_percent".to_string(), share(info.count, total.count));
+        map.insert("size".to_string(), Datum::Count(info.size as i64));
+        map.insert("size_percent".to_string(), share(info.size, total.size));
+        if info.count > 0 {
+            map.insert("avg_size".to_string(), Datum::Stat(info.size as f64 / info.count as f64));
+        }
+        map.insert("writes".to_string(), Datum::Count(info.writes as i64));
+        map.insert("writes_percent".to_string(), share(info.writes, total.writes));
+        if let Some(duration) = self.duration {
+            map.insert("writes_per_second".to_string(), Datum::Stat(info.writes as f64 / duration as f64));
+        }
+        if let Some(value_type) = info.value_type {
+            map.insert("type".to_string(), Datum::Text(value_type.to_string()));
+        }
+        map
+    }
+
+    fn keys(&self, info: &AofNodeInfo) -> u64 {
+        info.keys
+    }
+}
+
+/// Builds per-prefix counts, sizes and writes of keys.
+pub fn build_results(analysis: &AofAnalysis, options: &ResultsOptions) -> Results {
+    let merged = PrefixMap::new(
+        None,
+        analysis.keyspaces.iter().map(|(keyspace, keys)| {
+            let mut prefix_map = PrefixMap::default();
+            for (key, info) in keys {
+                prefix_map.insert(key.clone(), AofNodeInfo::from_key(info));
+            }
+            (keyspace.to_string().into_bytes(), prefix_map.simplify())
+        }).collect(),
+    ).sum_values(&AofNodeInfo::add);
+
+    let duration = analysis.duration();
+    let mut columns = vec![
+        "count".to_string(),
+        "count_percent".to_string(),
+        "size".to_string(),
+        "size_percent".to_string(),
+        "avg_size".to_string(),
+        "writes".to_string(),
+        "writes_percent".to_string(),
+    ];
+    if duration.is_some() {
+        columns.push("writes_per_second".to_string());
+    }
+    columns.push("type".to_string());
+
+    Results::from_prefix_map(&merged, &AofColumns { duration }, columns, SortOrder::descending("size"), options)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keyspace_info::KeyspaceId;
+    use super::{parse_manifest, AofAnalysis};
+
+    fn command(arguments: &[&str]) -> Vec<u8> {
+        let mut command = format!("*{}\r\n", arguments.len());
+        for argument in arguments {
+            command.push_str(&format!("${}\r\n{}\r\n", argument.len(), argument));
+        }
+        command.into_bytes()
+    }
+
+    fn replay(commands: &[&[&str]]) -> AofAnalysis {
+        let mut aof = b"#TS:1700000000\r\n".to_vec();
+        for arguments in commands {
+            aof.extend(command(arguments));
+        }
+        aof.extend_from_slice(b"#TS:1700000010\r\n");
+        let mut analysis = AofAnalysis::default();
+        analysis.read_commands(&mut &aof[..]).unwrap();
+        analysis
+    }
+
+    #[test]
+    fn test_replay() {
+        let analysis = replay(&[
+            &["SELECT", "0"],
+            &["SET", "session:1", "abcd"],
+            &["SET", "session:1", "abcdef"],
+            &["HSET", "user:1", "name", "joe", "age", "42"],
+            &["HSET", "user:1", "name", "john"],
+            &["HDEL", "user:1", "age"],
+            &["RPUSH", "queue", "a", "bb", "ccc"],
+            &["LPOP", "queue", "3"],
+            &["SET", "tmp", "x"],
+            &["RENAME", "tmp", "moved"],
+            &["PEXPIREAT", "moved", "1700000100000"],
+            &["SELECT", "1"],
+            &["SADD", "set", "a", "b"],
+        ]);
+        assert_eq!(analysis.commands, 13);
+        assert_eq!(analysis.duration(), Some(10));
+
+        let keys = &analysis.keyspaces[&KeyspaceId::new(0)];
+        let session = &keys[&b"session:1".to_vec()];
+        assert_eq!((session.exists, session.size, session.writes), (true, 6, 2));
+        let user = &keys[&b"user:1".to_vec()];
+        assert_eq!((user.elements, user.size, user.writes), (1, 8, 3));
+        let queue = &keys[&b"queue".to_vec()];
+        assert_eq!((queue.exists, queue.writes), (false, 2));
+        assert!(!keys[&b"tmp".to_vec()].exists);
+        assert_eq!(keys[&b"moved".to_vec()].writes, 2);
+        assert_eq!(analysis.keyspaces[&KeyspaceId::new(1)][&b"set".to_vec()].elements, 2);
+    }
+
+    #[test]
+    fn test_pop_members() {
+        let analysis = replay(&[
+            &["ZADD", "zset", "1", "a", "2", "bb", "3", "ccc"],
+            &["ZPOPMIN", "zset", "2"],
+            &["ZADD", "gone", "1", "a"],
+            &["ZPOPMAX", "gone"],
+        ]);
+        let keys = &analysis.keyspaces[&KeyspaceId::new(0)];
+        let zset = &keys[&b"zset".to_vec()];
+        assert_eq!(zset.elements, 1);
+        assert_eq!(zset.size, zset.members.values().sum::<u64>());
+        assert!(!keys[&b"gone".to_vec()].exists);
+    }
+
+    #[test]
+    fn test_string_sizes() {
+        let analysis = replay(&[
+            &["SET", "overwritten", "abcdef"],
+            &["SETRANGE", "overwritten", "2", "xy"],
+            &["SET", "extended", "abc"],
+            &["SETRANGE", "extended", "2", "xyz"],
+            &["APPEND", "appended", "ab"],
+            &["APPEND", "appended", "cd"],
+        ]);
+        let keys = &analysis.keyspaces[&KeyspaceId::new(0)];
+        assert_eq!(keys[&b"overwritten".to_vec()].size, 6);
+        assert_eq!(keys[&b"extended".to_vec()].size, 5);
+        assert_eq!(keys[&b"appended".to_vec()].size, 4);
+    }
+
+    #[test]
+    fn test_malformed_commands() {
+        let analysis = replay(&[
+            &["SADD", "set", "a", "b"],
+            &["SMOVE", "set"],
+        ]);
+        assert_eq!(analysis.commands, 2);
+        assert_eq!(analysis.keyspaces[&KeyspaceId::new(0)][&b"set".to_vec()].elements, 2);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut aof = command(&["SET", "a", "1"]);
+        aof.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb");
+        let mut analysis = AofAnalysis::default();
+        analysis.read_commands(&mut &aof[..]).unwrap();
+        assert!(analysis.truncated);
+        assert_eq!(analysis.commands, 1);
+
+        // A huge length of a truncated argument is not allocated
+        let mut analysis = AofAnalysis::default();
+        analysis.read_commands(&mut &b"*1\r\n$18446744073709551613\r\nSET"[..]).unwrap();
+        assert!(analysis.truncated);
+        let mut analysis = AofAnalysis::default();
+        assert!(analysis.read_commands(&mut &b"*1\r\n$18446744073709551615\r\nSET"[..]).is_err());
+        assert!(analysis.read_commands(&mut &b"*1\r\n$1\r\nabc\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+            file appendonly.aof.1.incr.aof seq 1 type h\n\
+            file \"append only.aof.2.incr.aof\" seq 2 type i\n\
+            file appendonly.aof.3.incr.aof seq 3 type i\n";
+        assert_eq!(parse_manifest(manifest).unwrap(), vec![
+            "appendonly.aof.2.base.rdb",
+            "append only.aof.2.incr.aof",
+            "appendonly.aof.3.incr.aof",
+        ]);
+        assert!(parse_manifest("seq 1 type b").is_err());
+    }
+}
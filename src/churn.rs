@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use crate::analysis::ResultsOptions;
+use crate::error::{Error, Result};
+use crate::keyspace_info::KeyspaceId;
+use crate::prefix_map::{escape_key, PrefixMap};
+use crate::progress::Progress;
+use crate::redis::RedisConnection;
+use crate::results::{Datum, Results};
+use crate::results2::{NodeColumns, SortOrder};
+use crate::run_info::{RunInfo, SampleInfo};
+use crate::tui::SampledKeys;
+
+/// Column used for charts of the results.
+pub const CHART_COLUMN: &str = "events";
+
+/// Classes of `notify-keyspace-events` of changes of keys of all types, including expirations
+/// and evictions. `A` is an alias for them.
+const EVENT_CLASSES: &str = "g$lshzxetd";
+
+/// Which keyevent notifications are collected.
+#[derive(Debug, Clone, Copy)]
+pub struct Notifications {
+    /// Classes of `notify-keyspace-events` which must be enabled
+    classes: &'static str,
+    /// Patterns of channels of all databases
+    patterns: &'static [&'static str],
+}
+
+/// All changes of keys.
+pub const ALL_EVENTS: Notifications = Notifications { classes: EVENT_CLASSES, patterns: &["__keyevent@*__:*"] };
+
+/// Keys removed by the server, expired or evicted.
+pub const REMOVALS: Notifications = Notifications {
+    classes: "xe",
+    patterns: &["__keyevent@*__:expired", "__keyevent@*__:evicted"],
+};
+
+const CONFIG_PARAMETER: &str = "notify-keyspace-events";
+
+/// Numbers of keyevent notifications of a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyEvents {
+    /// Values written by `SET` and its variants
+    pub sets: u64,
+    pub dels: u64,
+    /// Expirations set on the key
+    pub expires: u64,
+    /// The key expired
+    pub expired: u64,
+    /// The key was evicted because of `maxmemory`
+    pub evicted: u64,
+    /// Other modifications (`hset`, `lpush`, `rename_from`, ...)
+    pub other: u64,
+}
+
+impl KeyEvents {
+    pub fn total(&self) -> u64 {
+        self.sets + self.dels + self.expires + self.expired + self.evicted + self.other
+    }
+
+    fn add_event(&mut self, event: &str) {
+        match event {
+            "set" => self.sets += 1,
+            "del" => self.dels += 1,
+            "expire" => self.expires += 1,
+            "expired" => self.expired += 1,
+            "evicted" => self.evicted += 1,
+            _ => self.other += 1,
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        KeyEvents {
+            sets: self.sets + other.sets,
+            dels: self.dels + other.dels,
+            expires: self.expires + other.expires,
+            expired: self.expired + other.expired,
+            evicted: self.evicted + other.evicted,
+            other: self.other + other.other,
+        }
+    }
+}
+
+/// Keyevent notifications aggregated per key.
+#[derive(Debug)]
+pub struct ChurnStats {
+    pub keyspaces: HashMap<KeyspaceId, HashMap<Vec<u8>, KeyEvents>>,
+    pub events: u64,
+    pub started_at: SystemTime,
+    /// Length of the capture in seconds
+    pub duration: f64,
+}
+
+impl Default for ChurnStats {
+    fn default() -> Self {
+        ChurnStats { keyspaces: HashMap::new(), events: 0, started_at: SystemTime::now(), duration: 0.0 }
+    }
+}
+
+/// Parses a channel like `__keyevent@0__:set` into the database and the event.
+fn parse_channel(channel: &[u8]) -> Option<(KeyspaceId, &str)> {
+    let channel = std::str::from_utf8(channel).ok()?;
+    let (db, event) = channel.strip_prefix("__keyevent@")?.split_once("__:")?;
+    Some((KeyspaceId::new(db.parse().ok()?), event))
+}
+
+/// Expands the `A` alias of `notify-keyspace-events` flags.
+fn expand_flags(flags: &str) -> String {
+    flags.replace('A', EVENT_CLASSES)
+}
+
+/// Classes of events which are not enabled by `flags`.
+fn missing_classes(flags: &str, classes: &str) -> String {
+    let flags = expand_flags(flags);
+    classes.chars().filter(|class| !flags.contains(*class)).collect()
+}
+
+/// Value of `notify-keyspace-events` which enables `classes` of keyevent notifications,
+/// `None` if `flags` already do.
+fn required_flags(flags: &str, classes: &str) -> Option<String> {
+    let mut missing = missing_classes(flags, classes);
+    if !flags.contains('E') {
+        missing.insert(0, 'E');
+    }
+    if missing.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}", flags, missing))
+}
+
+impl ChurnStats {
+    pub fn add(&mut self, db: KeyspaceId, event: &str, key: &[u8]) {
+        self.events += 1;
+        self.keyspaces.entry(db).or_default().entry(key.to_vec()).or_default().add_event(event);
+    }
+
+    pub fn run_info(&self, host: String, port: u16) -> RunInfo {
+        let mut keyspaces = self.keyspaces.iter().collect::<Vec<_>>();
+        keyspaces.sort_by_key(|(keyspace, _)| keyspace.as_i64());
+        RunInfo {
+            host,
+            port,
+            started_at: self.started_at,
+            samples: keyspaces.into_iter().map(|(keyspace, keys)| SampleInfo {
+                keyspace: *keyspace,
+                sample_size: keys.len() as u64,
+                total_keys: keys.len() as u64,
+                skipped: 0,
+            }).collect(),
+        }
+    }
+
+    /// Changed keys with their numbers of events.
+    pub fn sampled_keys(&self) -> SampledKeys {
+        self.keyspaces.iter().map(|(keyspace, keys)| {
+            (
+                keyspace.to_string(),
+                keys.iter().map(|(key, events)| (escape_key(key), events.total())).collect(),
+            )
+        }).collect()
+    }
+}
+
+/// Enables missing notification classes and restores the previous `notify-keyspace-events` when dropped.
+struct NotificationsGuard<'a> {
+    connection: RedisConnection<'a>,
+    previous: String,
+    progress: &'a dyn Progress,
+}
+
+impl Drop for NotificationsGuard<'_> {
+    fn drop(&mut self) {
+        self.progress.message(&match self.connection.config_set(CONFIG_PARAMETER, &self.previous) {
+            Ok(()) => format!("Restored {} to \"{}\"", CONFIG_PARAMETER, self.previous),
+            Err(e) => format!("Restoring {} to \"{}\" failed: {}", CONFIG_PARAMETER, self.previous, e),
+        });
+    }
+}
+
+/// Collects keyevent notifications of all databases for given time or until interrupted by Ctrl-C.
+/// With `enable_notifications`, missing notification classes are enabled for the capture. Progress is one unit per event.
+pub fn capture(host: &str, port: u16, duration: Duration, enable_notifications: bool, notifications: Notifications, progress: &dyn Progress) -> Result<ChurnStats> {
+    let mut config_connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    let flags = config_connection.config_get(CONFIG_PARAMETER)?.unwrap_or_default();
+    let _guard = match required_flags(&flags, notifications.classes) {
+        Some(required) if enable_notifications => {
+            config_connection.config_set(CONFIG_PARAMETER, &required)?;
+            progress.message(&format!("Enabled {} \"{}\" for the capture", CONFIG_PARAMETER, required));
+            Some(NotificationsGuard { connection: config_connection, previous: flags, progress })
+        },
+        Some(_) if !flags.contains('E') => return Err(Error::NotificationsDisabled { config: flags }),
+        Some(_) => {
+            progress.message(&format!("Some events are not notified (notify-keyspace-events is \"{}\"), use --enable-notifications to see all", flags));
+            None
+        },
+        None => None,
+    };
+
+    // Ctrl-C ends the capture early, the guard then restores the configuration
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        progress.message(&format!("Ctrl-C will not end the capture early: {}", e));
+    }
+
+    let connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    progress.message(&format!("Collecting keyspace events of {}:{} for {} seconds, Ctrl-C stops earlier", host, port, duration.as_secs_f64()));
+    progress.start(None);
+    let mut stats = ChurnStats::default();
+    let started = Instant::now();
+    connection.psubscribe(notifications.patterns, duration, &stop, |channel, key| {
+        if let Some((db, event)) = parse_channel(channel) {
+            stats.add(db, event, key);
+            progress.inc(1);
+        }
+    })?;
+    stats.duration = started.elapsed().as_secs_f64();
+    progress.finish();
+    Ok(stats)
+}
+
+/// Columns of nodes of the tree of notified keys (number of keys and their events), rates are per second of a capture of `seconds`.
+struct ChurnColumns {
+    seconds: f64,
+}
+
+impl NodeColumns<(u64, KeyEvents)> for ChurnColumns {
+    fn columns(&self, &(keys, events): &(u64, KeyEvents), (_, total): &(u64, KeyEvents)) -> HashMap<String, Datum> {
+        let mut map = HashMap::new();
+        map.insert("keys".to_string(), Datum::Count(keys as i64));
+        map.insert("sets".to_string(), Datum::Count(events.sets as i64));
+        map.insert("dels".to_string(), Datum::Count(events.dels as i64));
+        map.insert("expires".to_string(), Datum::Count(events.expires as i64));
+        map.insert("expired".to_string(), Datum::Count(events.expired as i64));
+        map.insert("evicted".to_string(), Datum::Count(events.evicted as i64));
+        map.insert("other_events".to_string(), Datum::Count(events.other as i64));
+        map.insert("events".to_string(), Datum::Count(events.total() as i64));
+        map.insert("events_percent".to_string(), Datum::Percent(events.total() as f64 / total.total().max(1) as f64));
+        if self.seconds > 0.0 {
+            map.insert("events_per_second".to_string(), Datum::Stat(events.total() as f64 / self.seconds));
+            map.insert("keys_per_second".to_string(), Datum::Stat(keys as f64 / self.seconds));
+        }
+        map
+    }
+
+    fn keys(&self, (keys, _): &(u64, KeyEvents)) -> u64 {
+        *keys
+    }
+}
+
+/// Builds per-prefix numbers and rates of events. `min_count` applies to the number of changed keys.
+pub fn build_results(stats: &ChurnStats, options: &ResultsOptions) -> Results {
+    let merged = PrefixMap::new(
+        None,
+        stats.keyspaces.iter().map(|(keyspace, keys)| {
+            let mut prefix_map = PrefixMap::default();
+            for (key, events) in keys {
+                prefix_map.insert(key.clone(), (1u64, *events));
+            }
+            (keyspace.to_string().into_bytes(), prefix_map.simplify())
+        }).collect(),
+    ).sum_values(&|(keys, events), (other_keys, other_events)| (keys + other_keys, events.add(other_events)));
+
+    let seconds = stats.duration;
+    let mut columns = ["keys", "sets", "dels", "expires", "expired", "evicted", "other_events", "events", "events_percent"]
+        .map(str::to_string)
+        .to_vec();
+    if seconds > 0.0 {
+        columns.extend(["events_per_second", "keys_per_second"].map(str::to_string));
+    }
+
+    Results::from_prefix_map(&merged, &ChurnColumns { seconds }, columns, SortOrder::descending(CHART_COLUMN), options)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analysis::ResultsOptions;
+    use crate::keyspace_info::KeyspaceId;
+    use crate::results::Datum;
+    use super::{build_results, parse_channel, required_flags, ChurnStats, KeyEvents, EVENT_CLASSES, REMOVALS};
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(parse_channel(b"__keyevent@3__:expired"), Some((KeyspaceId::new(3), "expired")));
+        assert_eq!(parse_channel(b"__keyspace@0__:foo"), None);
+        assert_eq!(parse_channel(b"__keyevent@x__:set"), None);
+    }
+
+    #[test]
+    fn test_required_flags() {
+        assert_eq!(required_flags("", EVENT_CLASSES), Some("Eg$lshzxetd".to_string()));
+        assert_eq!(required_flags("KEA", EVENT_CLASSES), None);
+        assert_eq!(required_flags("Eg$lshzxetd", EVENT_CLASSES), None);
+        assert_eq!(required_flags("Ex", EVENT_CLASSES), Some("Exg$lshzetd".to_string()));
+        assert_eq!(required_flags("KA", EVENT_CLASSES), Some("KAE".to_string()));
+        assert_eq!(required_flags("Ex", REMOVALS.classes), Some("Exe".to_string()));
+        assert_eq!(required_flags("AE", REMOVALS.classes), None);
+    }
+
+    #[test]
+    fn test_churn() {
+        let mut stats = ChurnStats { duration: 2.0, ..Default::default() };
+        let db = KeyspaceId::new(0);
+        for (event, key) in [("set", "session:1"), ("expire", "session:1"), ("expired", "session:1"), ("set", "session:2"), ("hset", "user:1"), ("evicted", "user:2")] {
+            stats.add(db, event, key.as_bytes());
+        }
+        assert_eq!(stats.events, 6);
+        assert_eq!(stats.keyspaces[&db][b"session:1".as_slice()], KeyEvents { sets: 1, expires: 1, expired: 1, ..Default::default() });
+
+        let results = build_results(&stats, &ResultsOptions { min_count: 1, ..Default::default() });
+        let keyspace = &results.items[0];
+        assert!(matches!(keyspace.columns["events"], Datum::Count(6)));
+        assert!(matches!(keyspace.columns["keys"], Datum::Count(4)));
+        let session = &keyspace.children[0];
+        assert_eq!(session.name, "session:");
+        assert!(matches!(session.columns["sets"], Datum::Count(2)));
+        assert!(matches!(session.columns["events_per_second"], Datum::Stat(e) if e == 2.0));
+        assert!(matches!(session.columns["events_percent"], Datum::Percent(p) if p == 4.0 / 6.0));
+        assert!(matches!(keyspace.children[1].columns["evicted"], Datum::Count(1)));
+    }
+}
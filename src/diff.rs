@@ -0,0 +1,197 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::ops::Add;
+use crate::analysis::ResultsOptions;
+use crate::prefix_map::PrefixMap;
+use crate::results::{Datum, Item, Results};
+use crate::results2::{NodeColumns, SortOrder};
+use crate::snapshot::Snapshot;
+
+/// Column used for charts of a diff.
+pub const CHART_COLUMN: &str = "new_estimated_total_memory_usage";
+
+/// Estimates of keys under a prefix in the old and the new snapshot.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DiffNodeInfo {
+    old_count: u64,
+    new_count: u64,
+    old_estimated_count: f64,
+    new_estimated_count: f64,
+    old_estimated_memory_usage: f64,
+    new_estimated_memory_usage: f64,
+}
+
+impl Add for DiffNodeInfo {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            old_count: self.old_count + other.old_count,
+            new_count: self.new_count + other.new_count,
+            old_estimated_count: self.old_estimated_count + other.old_estimated_count,
+            new_estimated_count: self.new_estimated_count + other.new_estimated_count,
+            old_estimated_memory_usage: self.old_estimated_memory_usage + other.old_estimated_memory_usage,
+            new_estimated_memory_usage: self.new_estimated_memory_usage + other.new_estimated_memory_usage,
+        }
+    }
+}
+
+impl DiffNodeInfo {
+    /// Estimates of an item of the old and the new results, an item missing in the results has none.
+    pub(crate) fn of_items(old: Option<&Item>, new: Option<&Item>) -> Self {
+        let value = |item: Option<&Item>, column: &str| item.and_then(|item| item.columns.get(column)).map_or(0.0, Datum::value);
+        DiffNodeInfo {
+            old_count: value(old, "count") as u64,
+            new_count: value(new, "count") as u64,
+            old_estimated_count: value(old, "estimated_total_count"),
+            new_estimated_count: value(new, "estimated_total_count"),
+            old_estimated_memory_usage: value(old, "estimated_total_memory_usage"),
+            new_estimated_memory_usage: value(new, "estimated_total_memory_usage"),
+        }
+    }
+
+    fn memory_delta(&self) -> f64 {
+        self.new_estimated_memory_usage - self.old_estimated_memory_usage
+    }
+
+    fn count_delta(&self) -> f64 {
+        self.new_estimated_count - self.old_estimated_count
+    }
+}
+
+/// Relative change, `None` if there was nothing before.
+fn relative(old: f64, new: f64) -> Option<Datum> {
+    if old > 0.0 {
+        Some(Datum::Percent((new - old) / old))
+    } else {
+        None
+    }
+}
+
+/// Adds sampled keys of the snapshot to per-keyspace maps of keys.
+fn add_keys(keyspaces: &mut HashMap<Vec<u8>, HashMap<Vec<u8>, DiffNodeInfo>>, snapshot: &Snapshot, is_new: bool) {
+    for sample in &snapshot.samples {
+        let analyzed_share = sample.analyzed_share();
+        let keys = keyspaces.entry(sample.keyspace.to_string().into_bytes()).or_default();
+        for key in &sample.keys {
+            let info = keys.entry(key.key.clone()).or_default();
+            let estimated_memory_usage = key.memory_usage as f64 / analyzed_share;
+            if is_new {
+                info.new_count += 1;
+                info.new_estimated_count += 1.0 / analyzed_share;
+                info.new_estimated_memory_usage += estimated_memory_usage;
+            } else {
+                info.old_count += 1;
+                info.old_estimated_count += 1.0 / analyzed_share;
+                info.old_estimated_memory_usage += estimated_memory_usage;
+            }
+        }
+    }
+}
+
+/// Columns of the comparison in the order of the output.
+pub(crate) const COLUMNS: [&str; 8] = [
+    "old_estimated_total_count",
+    "new_estimated_total_count",
+    "count_delta",
+    "count_delta_percent",
+    "old_estimated_total_memory_usage",
+    "new_estimated_total_memory_usage",
+    "memory_usage_delta",
+    "memory_usage_delta_percent",
+];
+
+/// Columns of nodes of the merged tree of keys of both snapshots, or of merged items of two results.
+pub(crate) struct DiffColumns;
+
+impl NodeColumns<DiffNodeInfo> for DiffColumns {
+    fn columns(&self, info: &DiffNodeInfo, _parent: &DiffNodeInfo) -> HashMap<String, Datum> {
+        let mut map = HashMap::new();
+        map.insert("old_estimated_total_count".to_string(), Datum::Stat(info.old_estimated_count));
+        map.insert("new_estimated_total_count".to_string(), Datum::Stat(info.new_estimated_count));
+        map.insert("count_delta".to_string(), Datum::Stat(info.count_delta()));
+        if let Some(datum) = relative(info.old_estimated_count, info.new_estimated_count) {
+            map.insert("count_delta_percent".to_string(), datum);
+        }
+        map.insert("old_estimated_total_memory_usage".to_string(), Datum::Stat(info.old_estimated_memory_usage));
+        map.insert("new_estimated_total_memory_usage".to_string(), Datum::Stat(info.new_estimated_memory_usage));
+        map.insert("memory_usage_delta".to_string(), Datum::Stat(info.memory_delta()));
+        if let Some(datum) = relative(info.old_estimated_memory_usage, info.new_estimated_memory_usage) {
+            map.insert("memory_usage_delta_percent".to_string(), datum);
+        }
+        map
+    }
+
+    fn keys(&self, info: &DiffNodeInfo) -> u64 {
+        max(info.old_count, info.new_count)
+    }
+}
+
+/// Compares estimates of two snapshots, prefixes are sorted by growth of estimated memory usage.
+pub fn build_diff(old: &Snapshot, new: &Snapshot, options: &ResultsOptions) -> Results {
+    let mut keyspaces = HashMap::new();
+    add_keys(&mut keyspaces, old, false);
+    add_keys(&mut keyspaces, new, true);
+
+    let merged = PrefixMap::new(
+        None,
+        keyspaces.into_iter().map(|(keyspace, keys)| {
+            let mut prefix_map = PrefixMap::default();
+            for (key, info) in keys {
+                prefix_map.insert(key, info);
+            }
+            (keyspace, prefix_map.simplify())
+        }).collect(),
+    ).sum_values(&|total, info| total + info);
+
+    let columns = COLUMNS.map(str::to_string).to_vec();
+    Results::from_prefix_map(&merged, &DiffColumns, columns, SortOrder::descending("memory_usage_delta"), options)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use crate::analysis::ResultsOptions;
+    use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+    use crate::results::Datum;
+    use crate::snapshot::{KeySample, KeyspaceSample, Snapshot};
+    use super::build_diff;
+
+    fn snapshot(total_keys: u64, keys: &[(&str, u64)]) -> Snapshot {
+        let info = KeyspaceInfo::new(total_keys, 0, 0);
+        let mut snapshot = Snapshot::new(
+            "localhost".to_string(),
+            6379,
+            BTreeMap::new(),
+            KeyspacesInfo { keyspaces: HashMap::from([(KeyspaceId::new(0), info.clone())]) },
+        );
+        snapshot.samples.push(KeyspaceSample {
+            keyspace: KeyspaceId::new(0),
+            info,
+            sample_size: keys.len() as u64,
+            keys: keys.iter()
+                .map(|(key, memory_usage)| KeySample::new(key.as_bytes().to_vec(), *memory_usage))
+                .collect(),
+            skipped: Default::default(),
+            matching_keys: None,
+        });
+        snapshot
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = snapshot(4, &[("foo:1", 10), ("foo:2", 10), ("bar:1", 50), ("bar:2", 50)]);
+        let new = snapshot(8, &[("foo:3", 100), ("foo:4", 100), ("bar:1", 50), ("baz", 20)]);
+        let results = build_diff(&old, &new, &ResultsOptions::default());
+
+        let keyspace = &results.items[0];
+        assert!(matches!(keyspace.columns["old_estimated_total_memory_usage"], Datum::Stat(m) if m == 120.0));
+        assert!(matches!(keyspace.columns["new_estimated_total_memory_usage"], Datum::Stat(m) if m == 540.0));
+        assert!(matches!(keyspace.columns["count_delta_percent"], Datum::Percent(p) if p == 1.0));
+
+        let names = keyspace.children.iter().map(|item| item.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["foo:", "baz", "bar:"]);
+        let baz = &keyspace.children[1];
+        assert!(matches!(baz.columns["memory_usage_delta"], Datum::Stat(m) if m == 40.0));
+        assert!(!baz.columns.contains_key("memory_usage_delta_percent"));
+    }
+}
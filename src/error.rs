@@ -0,0 +1,101 @@
+use std::fmt::{Display, Formatter};
+use redis::RedisError;
+
+/// Error of an analysis which cannot continue.
+#[derive(Debug)]
+pub enum Error {
+    /// Connection failed or the server refused a command needed for the analysis
+    Redis(RedisError),
+    /// Reading or writing a file or the output failed
+    Io(std::io::Error),
+    /// Query of the history database failed
+    Sqlite(rusqlite::Error),
+    /// Keyevent notifications are disabled, `config` is the value of `notify-keyspace-events`
+    NotificationsDisabled { config: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Redis(e) => write!(f, "Redis query failed: {}", e),
+            Error::Io(e) => e.fmt(f),
+            Error::Sqlite(e) => write!(f, "History database query failed: {}", e),
+            Error::NotificationsDisabled { config } => write!(
+                f,
+                "Keyevent notifications are disabled (notify-keyspace-events is \"{}\"), enable them or use --enable-notifications",
+                config,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Redis(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Sqlite(e) => Some(e),
+            Error::NotificationsDisabled { .. } => None,
+        }
+    }
+}
+
+impl From<RedisError> for Error {
+    fn from(e: RedisError) -> Self {
+        Error::Redis(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Why a sampled key was not analyzed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The key was deleted or expired between `SCAN` and reading it
+    Vanished,
+    /// ACL of the user does not allow reading the key
+    NoPermission,
+}
+
+impl SkipReason {
+    /// Returns why a key is skipped if a command on it failed only because of the key itself.
+    pub fn of(e: &RedisError) -> Option<SkipReason> {
+        match e.code() {
+            Some("NOPERM") => Some(SkipReason::NoPermission),
+            Some("ERR") if e.detail().is_some_and(|detail| detail.contains("no such key")) => Some(SkipReason::Vanished),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use redis::{parse_redis_value, RedisError};
+    use super::SkipReason;
+
+    fn server_error(reply: &[u8]) -> RedisError {
+        parse_redis_value(reply).unwrap_err()
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let no_perm = server_error(b"-NOPERM this user has no permissions to access one of the keys used as arguments\r\n");
+        assert_eq!(SkipReason::of(&no_perm), Some(SkipReason::NoPermission));
+        assert_eq!(SkipReason::of(&server_error(b"-ERR no such key\r\n")), Some(SkipReason::Vanished));
+        assert_eq!(SkipReason::of(&server_error(b"-ERR unknown command 'MEMORY'\r\n")), None);
+        let io = RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert_eq!(SkipReason::of(&io), None);
+    }
+}
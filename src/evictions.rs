@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::analysis::ResultsOptions;
+use crate::churn::{self, ChurnStats};
+use crate::error::Result;
+use crate::progress::Progress;
+use crate::keyspace_info::KeyspaceId;
+use crate::redis::RedisConnection;
+use crate::results::{Datum, Item, Results};
+
+/// Column used for charts and sorting of the results.
+pub const CHART_COLUMN: &str = "evicted";
+
+/// Counters of removed keys from `INFO stats`, they count since the start of the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RemovalCounters {
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+}
+
+impl RemovalCounters {
+    pub fn from_info(info: &BTreeMap<String, String>) -> RemovalCounters {
+        let counter = |name: &str| info.get(name).and_then(|value| value.parse().ok()).unwrap_or(0);
+        RemovalCounters { expired_keys: counter("expired_keys"), evicted_keys: counter("evicted_keys") }
+    }
+}
+
+/// Expired and evicted keys seen in notifications, with the counters of the server around the capture.
+#[derive(Debug)]
+pub struct RemovalStats {
+    pub events: ChurnStats,
+    pub before: RemovalCounters,
+    pub after: RemovalCounters,
+}
+
+impl RemovalStats {
+    /// Keys expired by the server during the capture, `CONFIG RESETSTAT` makes it 0.
+    pub fn server_expired(&self) -> u64 {
+        self.after.expired_keys.saturating_sub(self.before.expired_keys)
+    }
+
+    /// Keys evicted by the server during the capture.
+    pub fn server_evicted(&self) -> u64 {
+        self.after.evicted_keys.saturating_sub(self.before.evicted_keys)
+    }
+
+    fn notified(&self) -> (u64, u64) {
+        self.events.keyspaces.values().flat_map(|keys| keys.values()).fold((0, 0), |(expired, evicted), events| {
+            (expired + events.expired, evicted + events.evicted)
+        })
+    }
+}
+
+/// Share of a server counter attributed to prefixes by notifications.
+fn attributed(notified: u64, server: u64) -> String {
+    if server == 0 {
+        return format!("{} notified", notified);
+    }
+    format!("{} notified ({:.1}%)", notified, notified as f64 / server as f64 * 100.0)
+}
+
+impl Display for RemovalStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (expired, evicted) = self.notified();
+        writeln!(f, "Expired during the capture: {} keys by INFO stats, {}", self.server_expired(), attributed(expired, self.server_expired()))?;
+        writeln!(f, "Evicted during the capture: {} keys by INFO stats, {}", self.server_evicted(), attributed(evicted, self.server_evicted()))?;
+        write!(f, "Since the start of the server: {} expired, {} evicted keys", self.after.expired_keys, self.after.evicted_keys)
+    }
+}
+
+/// Collects notifications of expired and evicted keys and reads the counters of the server before and after.
+pub fn capture(host: &str, port: u16, duration: Duration, enable_notifications: bool, progress: &dyn Progress) -> Result<RemovalStats> {
+    let mut connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    let before = RemovalCounters::from_info(&connection.info("stats")?);
+    let events = churn::capture(host, port, duration, enable_notifications, churn::REMOVALS, progress)?;
+    let after = RemovalCounters::from_info(&connection.info("stats")?);
+    Ok(RemovalStats { events, before, after })
+}
+
+/// Builds per-prefix numbers of expired and evicted keys and their shares of the counters of the server.
+pub fn build_results(stats: &RemovalStats, options: &ResultsOptions) -> Results {
+    fn add_columns(items: &mut [Item], server_expired: u64, server_evicted: u64, seconds: f64) {
+        for item in items {
+            let count = |name: &str| item.columns.get(name).map_or(0.0, Datum::value);
+            let (expired, evicted) = (count("expired"), count("evicted"));
+            if server_expired > 0 {
+                item.columns.insert("expired_share".to_string(), Datum::Percent(expired / server_expired as f64));
+            }
+            if server_evicted > 0 {
+                item.columns.insert("evicted_share".to_string(), Datum::Percent(evicted / server_evicted as f64));
+            }
+            if seconds > 0.0 {
+                item.columns.insert("expired_per_second".to_string(), Datum::Stat(expired / seconds));
+                item.columns.insert("evicted_per_second".to_string(), Datum::Stat(evicted / seconds));
+            }
+            add_columns(&mut item.children, server_expired, server_evicted, seconds);
+        }
+    }
+
+    let mut results = churn::build_results(&stats.events, options);
+    let (server_expired, server_evicted, seconds) = (stats.server_expired(), stats.server_evicted(), stats.events.duration);
+    let mut columns = vec!["keys", "expired", "evicted"];
+    if server_expired > 0 {
+        columns.push("expired_share");
+    }
+    if server_evicted > 0 {
+        columns.push("evicted_share");
+    }
+    if seconds > 0.0 {
+        columns.extend(["expired_per_second", "evicted_per_second"]);
+    }
+    results.columns = columns.into_iter().map(str::to_string).collect();
+    add_columns(&mut results.items, server_expired, server_evicted, seconds);
+    results
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use crate::analysis::ResultsOptions;
+    use crate::churn::ChurnStats;
+    use crate::keyspace_info::KeyspaceId;
+    use crate::results::Datum;
+    use super::{build_results, RemovalCounters, RemovalStats};
+
+    #[test]
+    fn test_counters() {
+        let info = BTreeMap::from([
+            ("expired_keys".to_string(), "12".to_string()),
+            ("evicted_keys".to_string(), "3".to_string()),
+            ("total_commands_processed".to_string(), "100".to_string()),
+        ]);
+        assert_eq!(RemovalCounters::from_info(&info), RemovalCounters { expired_keys: 12, evicted_keys: 3 });
+        assert_eq!(RemovalCounters::from_info(&BTreeMap::new()), RemovalCounters::default());
+    }
+
+    #[test]
+    fn test_attribution() {
+        let mut events = ChurnStats { duration: 10.0, ..Default::default() };
+        let db = KeyspaceId::new(0);
+        for (event, key) in [("evicted", "cache:1"), ("evicted", "cache:2"), ("evicted", "cache:3"), ("expired", "session:1"), ("evicted", "session:2")] {
+            events.add(db, event, key.as_bytes());
+        }
+        let stats = RemovalStats {
+            events,
+            before: RemovalCounters { expired_keys: 100, evicted_keys: 10 },
+            after: RemovalCounters { expired_keys: 102, evicted_keys: 15 },
+        };
+        assert_eq!((stats.server_expired(), stats.server_evicted()), (2, 5));
+        assert!(stats.to_string().starts_with("Expired during the capture: 2 keys by INFO stats, 1 notified (50.0%)\nEvicted during the capture: 5 keys by INFO stats, 4 notified (80.0%)\n"));
+
+        let results = build_results(&stats, &ResultsOptions { min_count: 1, ..Default::default() });
+        assert_eq!(results.columns, ["keys", "expired", "evicted", "expired_share", "evicted_share", "expired_per_second", "evicted_per_second"]);
+        let cache = &results.items[0].children[0];
+        assert_eq!(cache.name, "cache:");
+        assert!(matches!(cache.columns["evicted_share"], Datum::Percent(s) if s == 0.6));
+        assert!(matches!(cache.columns["evicted_per_second"], Datum::Stat(r) if r == 0.3));
+        let session = &results.items[0].children[1];
+        assert!(matches!(session.columns["expired_share"], Datum::Percent(s) if s == 0.5));
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Response, Server};
+use crate::analysis::{build_results, ResultsOptions};
+use crate::error::{Error, Result};
+use crate::prefix_map::is_prefix;
+use crate::progress::Progress;
+use crate::results::{Item, Results};
+use crate::results2::walk;
+use crate::sampling::{self, SamplingOptions};
+
+/// Per-prefix gauges: metric name, help and the column of the results they are taken from.
+const PREFIX_GAUGES: [(&str, &str, &str); 4] = [
+    ("redis_analyzer_keys", "Estimated number of keys", "estimated_total_count"),
+    ("redis_analyzer_memory_bytes", "Estimated memory usage of keys in bytes", "estimated_total_memory_usage"),
+    ("redis_analyzer_ttl_ratio", "Share of sampled keys with expiration", "ttl_percent"),
+    ("redis_analyzer_sampled_keys", "Number of sampled keys", "count"),
+];
+
+/// Metrics of the last analysis, scrapes are answered from it and never start sampling.
+#[derive(Debug, Default)]
+struct Cache {
+    /// Per-prefix metrics of the last successful analysis
+    metrics: String,
+    last_success: Option<SystemTime>,
+    /// Whether the last analysis succeeded
+    up: bool,
+    last_duration: Option<Duration>,
+    errors: u64,
+}
+
+/// What the exporter samples and how often.
+#[derive(Debug, Clone)]
+pub struct ExporterOptions {
+    pub host: String,
+    pub port: u16,
+    /// Address of the HTTP server (e.g. `0.0.0.0:9187`)
+    pub listen: String,
+    /// Time between starts of two analyses
+    pub interval: Duration,
+    pub sampling: SamplingOptions,
+    pub results: ResultsOptions,
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Prometheus text format of per-prefix gauges of the results, keyspaces are labelled by `db`,
+/// prefixes by `prefix` (empty for the whole keyspace). Whole keys are left out to limit cardinality.
+pub fn render_metrics(results: &Results) -> String {
+    let mut rows = Vec::new();
+    let mut db = "";
+    walk(&results.items, &mut |item: &&Item, depth| {
+        let prefix = if depth == 0 {
+            db = &item.name;
+            ""
+        } else if is_prefix(item.name.as_bytes()) {
+            &item.name
+        } else {
+            return false;
+        };
+        rows.push((format!("db=\"{}\",prefix=\"{}\"", escape_label(db), escape_label(prefix)), *item));
+        true
+    });
+
+    let mut out = String::new();
+    for (name, help, column) in PREFIX_GAUGES {
+        if !results.columns.iter().any(|c| c == column) {
+            continue;
+        }
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for (labels, item) in &rows {
+            if let Some(datum) = item.columns.get(column) {
+                writeln!(out, "{}{{{}}} {}", name, labels, datum.value()).unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// Metrics about the exporter itself.
+fn render_status(cache: &Cache) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, kind: &str, help: &str, value: f64| {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        writeln!(out, "{} {}", name, value).unwrap();
+    };
+    let last_success = cache.last_success.and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    gauge("redis_analyzer_up", "gauge", "Whether the last analysis succeeded", if cache.up { 1.0 } else { 0.0 });
+    if let Some(last_success) = last_success {
+        gauge("redis_analyzer_last_success_timestamp_seconds", "gauge", "Time of the last successful analysis", last_success.as_secs_f64());
+    }
+    if let Some(duration) = cache.last_duration {
+        gauge("redis_analyzer_analysis_duration_seconds", "gauge", "Duration of the last analysis", duration.as_secs_f64());
+    }
+    gauge("redis_analyzer_analysis_errors_total", "counter", "Number of failed analyses", cache.errors as f64);
+    out
+}
+
+/// Samples the server and renders the metrics.
+fn analyze(options: &ExporterOptions, progress: &dyn Progress) -> Result<String> {
+    let snapshot = sampling::sample(&options.host, options.port, &options.sampling, progress)?;
+    Ok(render_metrics(&build_results(&snapshot, &options.results)))
+}
+
+/// Runs analyses every `interval` in the background and serves their results on `/metrics`.
+pub fn run(options: ExporterOptions, progress: &dyn Progress) -> Result<()> {
+    let server = Server::http(&options.listen).map_err(|e| Error::Io(std::io::Error::other(e)))?;
+    progress.message(&format!("Serving metrics on http://{}/metrics", options.listen));
+    let cache = Mutex::new(Cache::default());
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| loop {
+            let started = Instant::now();
+            let result = analyze(&options, progress);
+            // Every field of the cache is valid on its own, so it is still served after a panic while it was locked
+            let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+            cache.up = result.is_ok();
+            match result {
+                Ok(metrics) => {
+                    cache.metrics = metrics;
+                    cache.last_success = Some(SystemTime::now());
+                },
+                Err(e) => {
+                    progress.message(&format!("Analysis failed, serving the previous results: {}", e));
+                    cache.errors += 1;
+                },
+            }
+            cache.last_duration = Some(started.elapsed());
+            drop(cache);
+            std::thread::sleep(options.interval.saturating_sub(started.elapsed()));
+        });
+
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+                Response::from_string(format!("{}{}", cache.metrics, render_status(&cache)))
+                    .with_header("Content-Type: text/plain; version=0.0.4".parse::<Header>().unwrap())
+            } else {
+                Response::from_string("Metrics are at /metrics\n").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                progress.message(&format!("Sending metrics failed: {}", e));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use crate::results::{Datum, Item, Results};
+    use super::{render_metrics, render_status, Cache};
+
+    fn item(name: &str, count: f64, ttl: f64, children: Vec<Item>) -> Item {
+        Item {
+            name: name.to_string(),
+            columns: HashMap::from([
+                ("estimated_total_count".to_string(), Datum::Stat(count)),
+                ("ttl_percent".to_string(), Datum::Percent(ttl)),
+            ]),
+            children,
+        }
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let results = Results {
+            columns: vec!["estimated_total_count".to_string(), "ttl_percent".to_string()],
+            items: vec![item("0", 100.0, 0.5, vec![
+                item("user:", 80.0, 0.25, vec![item("user:1", 1.0, 0.0, vec![])]),
+                item("say \"hi\":", 20.0, 1.0, vec![]),
+            ])],
+        };
+        let metrics = render_metrics(&results);
+        assert!(metrics.contains("# TYPE redis_analyzer_keys gauge\n"));
+        assert!(metrics.contains("redis_analyzer_keys{db=\"0\",prefix=\"\"} 100\n"));
+        assert!(metrics.contains("redis_analyzer_keys{db=\"0\",prefix=\"user:\"} 80\n"));
+        assert!(metrics.contains("redis_analyzer_ttl_ratio{db=\"0\",prefix=\"user:\"} 0.25\n"));
+        assert!(metrics.contains("prefix=\"say \\\"hi\\\":\""));
+        // Whole keys are not exported
+        assert!(!metrics.contains("user:1"));
+        // Columns missing in the results are not exported
+        assert!(!metrics.contains("redis_analyzer_memory_bytes"));
+    }
+
+    #[test]
+    fn test_render_status() {
+        let status = render_status(&Cache { errors: 2, ..Default::default() });
+        assert!(status.contains("redis_analyzer_up 0\n"));
+        assert!(status.contains("redis_analyzer_analysis_errors_total 2\n"));
+        assert!(!status.contains("last_success"));
+
+        // The last analysis failed after an earlier success
+        let status = render_status(&Cache { last_success: Some(SystemTime::now()), errors: 1, ..Default::default() });
+        assert!(status.contains("redis_analyzer_up 0\n"));
+        assert!(status.contains("redis_analyzer_last_success_timestamp_seconds"));
+        assert!(render_status(&Cache { last_success: Some(SystemTime::now()), up: true, ..Default::default() }).contains("redis_analyzer_up 1\n"));
+    }
+}
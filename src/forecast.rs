@@ -0,0 +1,185 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, UNIX_EPOCH};
+use crate::results::{Datum, Item, Results};
+use crate::run_info::format_timestamp;
+use crate::watch::{ItemPath, Run};
+
+/// Column with the fitted growth of estimated memory usage per hour.
+pub const TREND_COLUMN: &str = "memory_trend_per_hour";
+
+/// Slope of a least squares line through `(hours, value)` points, `None` without two distinct times.
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let variance = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+    if variance == 0.0 {
+        return None;
+    }
+    Some(points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / variance)
+}
+
+/// Hours since the first run.
+fn hours(runs: &[Run], run: &Run) -> f64 {
+    run.time.saturating_sub(runs[0].time) as f64 / 3600.0
+}
+
+/// Growth of `used_memory` of the whole server and when it reaches `maxmemory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceForecast {
+    /// Unix timestamp of the latest run in seconds
+    pub time: u64,
+    pub used_memory: u64,
+    pub growth_per_hour: f64,
+    /// `None` if the server has no memory limit
+    pub maxmemory: Option<u64>,
+}
+
+impl InstanceForecast {
+    /// Fits a trend of `used_memory` of the runs, `None` if it is not known for two runs at different times.
+    pub fn new(runs: &[Run], maxmemory: Option<u64>) -> Option<InstanceForecast> {
+        let points = runs.iter()
+            .filter_map(|run| run.used_memory.map(|used_memory| (hours(runs, run), used_memory as f64)))
+            .collect::<Vec<_>>();
+        let latest = runs.iter().rev().find(|run| run.used_memory.is_some())?;
+        Some(InstanceForecast {
+            time: latest.time,
+            used_memory: latest.used_memory?,
+            growth_per_hour: slope(&points)?,
+            maxmemory: maxmemory.filter(|maxmemory| *maxmemory > 0),
+        })
+    }
+
+    /// Time until `used_memory` reaches `maxmemory` if it keeps growing at the same rate,
+    /// `None` if it does not grow or maxmemory is too far to be reached.
+    pub fn time_to_maxmemory(&self) -> Option<Duration> {
+        let remaining = self.maxmemory?.saturating_sub(self.used_memory) as f64;
+        if self.growth_per_hour <= 0.0 {
+            return None;
+        }
+        Duration::try_from_secs_f64(remaining / self.growth_per_hour * 3600.0).ok()
+    }
+}
+
+impl Display for InstanceForecast {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "used_memory {} bytes, trend {:+.0} bytes per hour", self.used_memory, self.growth_per_hour)?;
+        let reached = self.time_to_maxmemory().and_then(|remaining| {
+            let at = UNIX_EPOCH.checked_add(Duration::from_secs(self.time))?.checked_add(remaining)?;
+            Some((remaining, at))
+        });
+        match (self.maxmemory, reached) {
+            (None, _) => write!(f, ", maxmemory is not set"),
+            (Some(maxmemory), None) => write!(f, ", maxmemory {} is not approached", maxmemory),
+            (Some(maxmemory), Some((remaining, at))) => write!(
+                f,
+                ", maxmemory {} reached in {:.1} days (around {})",
+                maxmemory,
+                remaining.as_secs_f64() / 86400.0,
+                format_timestamp(at),
+            ),
+        }
+    }
+}
+
+/// Adds the fitted growth of estimated memory usage per hour of every prefix and its share of the growth
+/// of all keyspaces to the results of the latest run, the shares show which prefixes drive the growth.
+pub fn add_forecast_columns(runs: &[Run], results: &mut Results) {
+    fn add_items(runs: &[Run], path: &mut ItemPath, items: &mut [Item], total_growth: f64) {
+        for item in items {
+            path.push(item.name.clone());
+            let points = runs.iter().map(|run| (hours(runs, run), run.estimate(path).memory_usage)).collect::<Vec<_>>();
+            if let Some(growth) = slope(&points) {
+                item.columns.insert(TREND_COLUMN.to_string(), Datum::Stat(growth));
+                if total_growth > 0.0 {
+                    item.columns.insert("growth_share".to_string(), Datum::Percent(growth / total_growth));
+                }
+            }
+            add_items(runs, path, &mut item.children, total_growth);
+            path.pop();
+        }
+    }
+
+    let keyspace_growth = results.items.iter()
+        .filter_map(|keyspace| {
+            let path = vec![keyspace.name.clone()];
+            slope(&runs.iter().map(|run| (hours(runs, run), run.estimate(&path).memory_usage)).collect::<Vec<_>>())
+        })
+        .collect::<Vec<_>>();
+    if keyspace_growth.is_empty() {
+        return;
+    }
+    let total_growth = keyspace_growth.iter().sum::<f64>();
+    results.columns.push(TREND_COLUMN.to_string());
+    if total_growth > 0.0 {
+        results.columns.push("growth_share".to_string());
+    }
+    add_items(runs, &mut Vec::new(), &mut results.items, total_growth);
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use crate::results::{Datum, Item, Results};
+    use crate::watch::Run;
+    use super::{add_forecast_columns, slope, InstanceForecast, TREND_COLUMN};
+
+    fn results(foo: f64, bar: f64) -> Results {
+        let item = |name: &str, memory_usage: f64, children| Item {
+            name: name.to_string(),
+            columns: HashMap::from([("estimated_total_memory_usage".to_string(), Datum::Stat(memory_usage))]),
+            children,
+        };
+        Results {
+            columns: vec!["estimated_total_memory_usage".to_string()],
+            items: vec![item("0", foo + bar, vec![item("foo:", foo, vec![]), item("bar:", bar, vec![])])],
+        }
+    }
+
+    fn run(hours: u64, foo: f64, bar: f64, used_memory: u64) -> Run {
+        Run { used_memory: Some(used_memory), ..Run::new(hours * 3600, &results(foo, bar)) }
+    }
+
+    #[test]
+    fn test_slope() {
+        assert_eq!(slope(&[(0.0, 1.0), (1.0, 3.0), (2.0, 5.0)]), Some(2.0));
+        assert_eq!(slope(&[(1.0, 1.0), (1.0, 3.0)]), None);
+    }
+
+    #[test]
+    fn test_forecast_columns() {
+        let runs = [run(0, 100.0, 50.0, 1000), run(1, 400.0, 50.0, 1300), run(2, 700.0, 50.0, 1600)];
+        let mut latest = results(700.0, 50.0);
+        add_forecast_columns(&runs, &mut latest);
+        let foo = &latest.items[0].children[0];
+        assert!(matches!(foo.columns[TREND_COLUMN], Datum::Stat(g) if g == 300.0));
+        assert!(matches!(foo.columns["growth_share"], Datum::Percent(s) if s == 1.0));
+        let bar = &latest.items[0].children[1];
+        assert!(matches!(bar.columns["growth_share"], Datum::Percent(s) if s == 0.0));
+    }
+
+    #[test]
+    fn test_time_to_maxmemory() {
+        let runs = [run(0, 0.0, 0.0, 1000), run(1, 0.0, 0.0, 1300), run(2, 0.0, 0.0, 1600)];
+        let forecast = InstanceForecast::new(&runs, Some(2200)).unwrap();
+        assert_eq!(forecast.growth_per_hour, 300.0);
+        assert_eq!(forecast.time_to_maxmemory(), Some(Duration::from_secs(2 * 3600)));
+        assert!(forecast.to_string().contains("reached in 0.1 days"));
+
+        let unlimited = InstanceForecast::new(&runs, Some(0)).unwrap();
+        assert_eq!(unlimited.time_to_maxmemory(), None);
+        assert!(InstanceForecast::new(&runs[..1], Some(2200)).is_none());
+    }
+
+    #[test]
+    fn test_far_maxmemory() {
+        let forecast = |growth_per_hour| InstanceForecast { time: 0, used_memory: 0, growth_per_hour, maxmemory: Some(u64::MAX) };
+        // Too far for a duration
+        assert_eq!(forecast(1e-300).time_to_maxmemory(), None);
+        assert!(forecast(1e-300).to_string().contains("is not approached"));
+        // A duration, but too far for a point in time
+        assert!(forecast(4320.0).time_to_maxmemory().is_some());
+        assert!(forecast(4320.0).to_string().contains("is not approached"));
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use crate::diff::{DiffColumns, DiffNodeInfo, COLUMNS};
+use crate::error::Result;
+use crate::results::{Datum, Item, Results};
+use crate::results2::{sorted, NodeColumns, SortOrder};
+use crate::run_info::format_timestamp;
+use crate::snapshot::Snapshot;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    created_at INTEGER NOT NULL,
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    used_memory INTEGER,
+    -- Names of columns of the results separated by commas, in the order of the output
+    columns TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS keyspaces (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    keyspace INTEGER NOT NULL,
+    keys INTEGER NOT NULL,
+    expires INTEGER NOT NULL,
+    sample_size INTEGER NOT NULL,
+    PRIMARY KEY (run_id, keyspace)
+);
+-- Items of the results, keyspaces have no parent
+CREATE TABLE IF NOT EXISTS nodes (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    parent_id INTEGER REFERENCES nodes(id),
+    keyspace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS nodes_by_name ON nodes(keyspace, name);
+CREATE INDEX IF NOT EXISTS nodes_by_run ON nodes(run_id);
+-- Children of a node in their order, `parent_id IS NULL` for keyspaces uses it too
+CREATE INDEX IF NOT EXISTS nodes_by_parent ON nodes(run_id, parent_id, position);
+CREATE TABLE IF NOT EXISTS metrics (
+    node_id INTEGER NOT NULL REFERENCES nodes(id),
+    name TEXT NOT NULL,
+    -- percent, count, stat or text
+    kind TEXT NOT NULL,
+    value REAL,
+    text TEXT,
+    PRIMARY KEY (node_id, name)
+);
+";
+
+/// Analysis stored in the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRun {
+    pub id: i64,
+    pub created_at: u64,
+    pub host: String,
+    pub port: u16,
+    pub used_memory: Option<u64>,
+}
+
+/// History of analyses in a SQLite database.
+pub struct HistoryStore {
+    connection: Connection,
+}
+
+fn datum_to_row(datum: &Datum) -> (&'static str, Option<f64>, Option<&str>) {
+    match datum {
+        Datum::Percent(p) => ("percent", Some(*p), None),
+        Datum::Count(c) => ("count", Some(*c as f64), None),
+        Datum::Stat(s) => ("stat", Some(*s), None),
+        Datum::Text(t) => ("text", None, Some(t.as_str())),
+    }
+}
+
+fn datum_from_row(kind: &str, value: Option<f64>, text: Option<String>) -> Datum {
+    match kind {
+        "percent" => Datum::Percent(value.unwrap_or(0.0)),
+        "count" => Datum::Count(value.unwrap_or(0.0) as i64),
+        "stat" => Datum::Stat(value.unwrap_or(0.0)),
+        _ => Datum::Text(text.unwrap_or_default()),
+    }
+}
+
+fn insert_items(transaction: &Transaction, run_id: i64, parent_id: Option<i64>, keyspace: Option<&str>, items: &[Item]) -> rusqlite::Result<()> {
+    for (position, item) in items.iter().enumerate() {
+        let keyspace = keyspace.unwrap_or(&item.name);
+        transaction.execute(
+            "INSERT INTO nodes (run_id, parent_id, keyspace, name, position) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run_id, parent_id, keyspace, item.name, position as i64],
+        )?;
+        let node_id = transaction.last_insert_rowid();
+        for (name, datum) in &item.columns {
+            let (kind, value, text) = datum_to_row(datum);
+            transaction.execute(
+                "INSERT INTO metrics (node_id, name, kind, value, text) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![node_id, name, kind, value, text],
+            )?;
+        }
+        insert_items(transaction, run_id, Some(node_id), Some(keyspace), &item.children)?;
+    }
+    Ok(())
+}
+
+/// Items of `old` and `new` merged by names, with the columns of a diff of both runs.
+fn compare_items(old: &[Item], new: &[Item]) -> Vec<Item> {
+    let old_by_name = old.iter().map(|item| (&item.name, item)).collect::<HashMap<_, _>>();
+    let new_names = new.iter().map(|item| &item.name).collect::<HashSet<_>>();
+    let pairs = new.iter().map(|item| (&item.name, old_by_name.get(&item.name).copied(), Some(item)))
+        .chain(old.iter().filter(|item| !new_names.contains(&item.name)).map(|item| (&item.name, Some(item), None)));
+    pairs.map(|(name, old_item, new_item)| {
+        let info = DiffNodeInfo::of_items(old_item, new_item);
+        Item {
+            name: name.clone(),
+            columns: DiffColumns.columns(&info, &info),
+            children: compare_items(
+                old_item.map_or(&[], |item| &item.children),
+                new_item.map_or(&[], |item| &item.children),
+            ),
+        }
+    }).collect()
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<HistoryStore> {
+        HistoryStore::from_connection(Connection::open(path)?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<HistoryStore> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(HistoryStore { connection })
+    }
+
+    /// Appends the results of an analysis of the snapshot, returns id of the new run.
+    pub fn add_run(&mut self, snapshot: &Snapshot, results: &Results) -> Result<i64> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO runs (created_at, host, port, used_memory, columns) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                snapshot.created_at as i64,
+                snapshot.host,
+                snapshot.port,
+                snapshot.memory_info.as_ref().map(|info| info.used_memory as i64),
+                results.columns.join(","),
+            ],
+        )?;
+        let run_id = transaction.last_insert_rowid();
+        for sample in &snapshot.samples {
+            transaction.execute(
+                "INSERT INTO keyspaces (run_id, keyspace, keys, expires, sample_size) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![run_id, sample.keyspace.as_i64(), sample.info.keys as i64, sample.info.expires as i64, sample.sample_size as i64],
+            )?;
+        }
+        insert_items(&transaction, run_id, None, None, &results.items)?;
+        transaction.commit()?;
+        Ok(run_id)
+    }
+
+    pub fn runs(&self) -> Result<Vec<StoredRun>> {
+        let mut statement = self.connection.prepare("SELECT id, created_at, host, port, used_memory FROM runs ORDER BY created_at, id")?;
+        let runs = statement.query_map([], |row| Ok(StoredRun {
+            id: row.get(0)?,
+            created_at: row.get::<_, i64>(1)? as u64,
+            host: row.get(2)?,
+            port: row.get(3)?,
+            used_memory: row.get::<_, Option<i64>>(4)?.map(|m| m as u64),
+        }))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(runs)
+    }
+
+    fn columns(&self, node_id: i64) -> rusqlite::Result<HashMap<String, Datum>> {
+        let mut statement = self.connection.prepare_cached("SELECT name, kind, value, text FROM metrics WHERE node_id = ?1")?;
+        let columns = statement.query_map([node_id], |row| {
+            Ok((row.get::<_, String>(0)?, datum_from_row(&row.get::<_, String>(1)?, row.get(2)?, row.get(3)?)))
+        })?.collect();
+        columns
+    }
+
+    fn items(&self, run_id: i64, parent_id: Option<i64>) -> rusqlite::Result<Vec<Item>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT id, name FROM nodes WHERE run_id = ?1 AND parent_id IS ?2 ORDER BY position",
+        )?;
+        let nodes = statement.query_map(params![run_id, parent_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        nodes.into_iter().map(|(id, name)| Ok(Item {
+            name,
+            columns: self.columns(id)?,
+            children: self.items(run_id, Some(id))?,
+        })).collect()
+    }
+
+    /// Results of a stored run, `None` if there is no such run.
+    pub fn results(&self, run_id: i64) -> Result<Option<Results>> {
+        let columns: Option<String> = self.connection
+            .query_row("SELECT columns FROM runs WHERE id = ?1", [run_id], |row| row.get(0))
+            .optional()?;
+        match columns {
+            Some(columns) => Ok(Some(Results {
+                columns: columns.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect(),
+                items: self.items(run_id, None)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// List of runs as results, one item per run.
+    pub fn runs_results(&self) -> Result<Results> {
+        let mut statement = self.connection.prepare(
+            "SELECT runs.id, SUM(metrics.value) FROM runs
+            LEFT JOIN nodes ON nodes.run_id = runs.id AND nodes.parent_id IS NULL
+            LEFT JOIN metrics ON metrics.node_id = nodes.id AND metrics.name = 'estimated_total_memory_usage'
+            GROUP BY runs.id",
+        )?;
+        let memory = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<f64>>(1)?)))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(Results {
+            columns: ["created_at", "server", "used_memory", "estimated_total_memory_usage"].map(str::to_string).to_vec(),
+            items: self.runs()?.into_iter().map(|run| {
+                let mut columns = HashMap::from([
+                    ("created_at".to_string(), Datum::Text(format_timestamp(UNIX_EPOCH + Duration::from_secs(run.created_at)))),
+                    ("server".to_string(), Datum::Text(format!("{}:{}", run.host, run.port))),
+                ]);
+                if let Some(used_memory) = run.used_memory {
+                    columns.insert("used_memory".to_string(), Datum::Count(used_memory as i64));
+                }
+                if let Some(Some(memory)) = memory.get(&run.id) {
+                    columns.insert("estimated_total_memory_usage".to_string(), Datum::Stat(*memory));
+                }
+                Item { name: run.id.to_string(), columns, children: vec![] }
+            }).collect(),
+        })
+    }
+
+    /// Metrics of a prefix (or the whole keyspace if `prefix` is empty) in all runs, one item per run.
+    pub fn prefix_results(&self, keyspace: &str, prefix: &str) -> Result<Results> {
+        let name = if prefix.is_empty() { keyspace } else { prefix };
+        let mut statement = self.connection.prepare(
+            "SELECT runs.id, runs.created_at, nodes.id FROM nodes JOIN runs ON runs.id = nodes.run_id
+            WHERE nodes.keyspace = ?1 AND nodes.name = ?2 AND (nodes.parent_id IS NULL) = ?3
+            ORDER BY runs.created_at, runs.id",
+        )?;
+        let nodes = statement.query_map(params![keyspace, name, prefix.is_empty()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)?))
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut columns = vec!["created_at".to_string()];
+        let mut items = Vec::with_capacity(nodes.len());
+        for (run_id, created_at, node_id) in nodes {
+            let mut item_columns = self.columns(node_id)?;
+            let mut names = item_columns.keys().filter(|name| !columns.contains(name)).cloned().collect::<Vec<_>>();
+            names.sort();
+            columns.extend(names);
+            item_columns.insert("created_at".to_string(), Datum::Text(format_timestamp(UNIX_EPOCH + Duration::from_secs(created_at))));
+            items.push(Item { name: run_id.to_string(), columns: item_columns, children: vec![] });
+        }
+        Ok(Results { columns, items })
+    }
+
+    /// Estimates of two runs side by side, prefixes are sorted by growth of their estimated memory usage.
+    pub fn compare(&self, old_id: i64, new_id: i64) -> Result<Option<Results>> {
+        let (old, new) = match (self.results(old_id)?, self.results(new_id)?) {
+            (Some(old), Some(new)) => (old, new),
+            _ => return Ok(None),
+        };
+        Ok(Some(Results {
+            columns: COLUMNS.map(str::to_string).to_vec(),
+            items: sorted(&compare_items(&old.items, &new.items), SortOrder::descending("memory_usage_delta")).iter().map(Item::from_tree).collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use rusqlite::Connection;
+    use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+    use crate::results::{Datum, Item, Results};
+    use crate::snapshot::Snapshot;
+    use super::HistoryStore;
+
+    fn snapshot(created_at: u64) -> Snapshot {
+        let info = KeyspaceInfo::new(10, 0, 0);
+        let mut snapshot = Snapshot::new("localhost".to_string(), 6379, BTreeMap::new(), KeyspacesInfo {
+            keyspaces: HashMap::from([(KeyspaceId::new(0), info)]),
+        });
+        snapshot.created_at = created_at;
+        snapshot
+    }
+
+    fn results(foo: f64) -> Results {
+        let item = |name: &str, memory_usage: f64, children| Item {
+            name: name.to_string(),
+            columns: HashMap::from([
+                ("estimated_total_count".to_string(), Datum::Stat(10.0)),
+                ("estimated_total_memory_usage".to_string(), Datum::Stat(memory_usage)),
+                ("type".to_string(), Datum::Text("hash".to_string())),
+            ]),
+            children,
+        };
+        Results {
+            columns: vec!["estimated_total_count".to_string(), "estimated_total_memory_usage".to_string(), "type".to_string()],
+            items: vec![item("0", foo + 100.0, vec![item("foo:", foo, vec![item("foo:1", foo, vec![])]), item("bar", 100.0, vec![])])],
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut store = HistoryStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let first = store.add_run(&snapshot(1000), &results(500.0)).unwrap();
+        let second = store.add_run(&snapshot(2000), &results(800.0)).unwrap();
+
+        let runs = store.runs().unwrap();
+        assert_eq!(runs.iter().map(|run| run.id).collect::<Vec<_>>(), vec![first, second]);
+        let listed = store.runs_results().unwrap();
+        assert!(matches!(listed.items[1].columns["estimated_total_memory_usage"], Datum::Stat(m) if m == 900.0));
+
+        let loaded = store.results(first).unwrap().unwrap();
+        assert_eq!(loaded.columns, results(500.0).columns);
+        assert_eq!(loaded.items[0].children[0].name, "foo:");
+        assert_eq!(loaded.items[0].children[0].children[0].name, "foo:1");
+        assert!(matches!(&loaded.items[0].columns["type"], Datum::Text(t) if t == "hash"));
+
+        let series = store.prefix_results("0", "foo:").unwrap();
+        assert_eq!(series.items.len(), 2);
+        assert!(matches!(series.items[1].columns["estimated_total_memory_usage"], Datum::Stat(m) if m == 800.0));
+        assert_eq!(store.prefix_results("0", "").unwrap().items.len(), 2);
+
+        let compared = store.compare(first, second).unwrap().unwrap();
+        let foo = &compared.items[0].children[0];
+        assert_eq!(foo.name, "foo:");
+        assert!(matches!(foo.columns["memory_usage_delta"], Datum::Stat(d) if d == 300.0));
+        assert!(matches!(foo.columns["memory_usage_delta_percent"], Datum::Percent(p) if p == 0.6));
+        assert!(store.compare(first, 42).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_children_index() {
+        let store = HistoryStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let plan = store.connection
+            .query_row("EXPLAIN QUERY PLAN SELECT id, name FROM nodes WHERE run_id = 1 AND parent_id IS NULL ORDER BY position", [], |row| row.get::<_, String>(3))
+            .unwrap();
+        assert!(plan.contains("nodes_by_parent"), "{}", plan);
+    }
+}
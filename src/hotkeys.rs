@@ -0,0 +1,59 @@
+use std::time::Duration;
+use crate::error::Result;
+use crate::keyspace_info::KeyspaceId;
+use crate::metrics::Frequency;
+use crate::monitor::{self, AccessStats};
+use crate::progress::Progress;
+use crate::redis::RedisConnection;
+use crate::sampling::{self, SamplingOptions};
+use crate::snapshot::Snapshot;
+
+/// Source of information about hot keys.
+pub enum HotKeys {
+    /// Sampled keys with LFU frequency counters
+    Lfu(Box<Snapshot>),
+    /// Accesses captured by `MONITOR`
+    Monitor(AccessStats),
+}
+
+/// `OBJECT FREQ` works only with `allkeys-lfu` and `volatile-lfu` policies.
+fn is_lfu_policy(policy: &str) -> bool {
+    policy.ends_with("-lfu")
+}
+
+/// Finds hot keys from LFU counters of sampled keys if the eviction policy allows it,
+/// falls back to capturing accesses by `MONITOR` for `monitor_duration` otherwise.
+pub fn find_hot_keys(host: &str, port: u16, options: SamplingOptions, monitor_duration: Duration, progress: &dyn Progress) -> Result<HotKeys> {
+    let mut connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    // Managed services often rename or disable CONFIG, INFO memory has the policy too
+    let policy = match connection.config_get("maxmemory-policy") {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(e) => {
+            progress.message(&format!("CONFIG GET maxmemory-policy failed, using maxmemory_policy from INFO memory: {}", e));
+            connection.memory_info()?.maxmemory_policy
+        },
+    };
+    if is_lfu_policy(&policy) {
+        let options = options.with_metric(&Frequency);
+        return Ok(HotKeys::Lfu(Box::new(sampling::sample(host, port, &options, progress)?)));
+    }
+    progress.message(&format!(
+        "maxmemory-policy is '{}', LFU access counters (OBJECT FREQ) are kept only with allkeys-lfu or volatile-lfu. \
+        Falling back to sampling accesses with MONITOR.",
+        policy,
+    ));
+    Ok(HotKeys::Monitor(monitor::capture(host, port, monitor_duration, progress)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_lfu_policy;
+
+    #[test]
+    fn test_lfu_policy() {
+        assert!(is_lfu_policy("allkeys-lfu"));
+        assert!(is_lfu_policy("volatile-lfu"));
+        assert!(!is_lfu_policy("allkeys-lru"));
+        assert!(!is_lfu_policy("noeviction"));
+    }
+}
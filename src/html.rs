@@ -0,0 +1,152 @@
+use std::io::Write;
+use serde_json::{json, Map, Value};
+use crate::results::Results;
+use crate::results2::Tree;
+use crate::run_info::{format_timestamp, RunInfo};
+
+const TEMPLATE: &str = include_str!("html/report.html");
+const DATA_PLACEHOLDER: &str = "/*REPORT_DATA*/null";
+
+/// Prefix of labels of top-level nodes which are keyspaces (e.g. `db0`).
+pub const KEYSPACE_LABEL_PREFIX: &str = "db";
+
+/// Converts a node with its descendants, `top` is the label of the top-level node above it.
+///
+/// Names of nodes are already full prefixes, so labels only add the top-level node (e.g. `db0 foo:1`).
+fn node_to_json<T: Tree>(node: &T, label_prefix: &str, top: Option<&str>) -> Value {
+    let name = node.name();
+    let label = match top {
+        Some(top) => format!("{} {}", top, name),
+        None => format!("{}{}", label_prefix, name),
+    };
+    let top = top.unwrap_or(&label);
+    let columns = node.columns().into_iter()
+        .map(|(column, datum)| {
+            let value = datum.value();
+            (
+                column,
+                json!({
+                    "value": if value.is_finite() { value } else { 0.0 },
+                    "text": datum.to_string(),
+                }),
+            )
+        })
+        .collect::<Map<_, _>>();
+    json!({
+        "name": name,
+        "label": label,
+        "columns": columns,
+        "children": node.children().into_iter().map(|child| node_to_json(&child, label_prefix, Some(top))).collect::<Vec<_>>(),
+    })
+}
+
+/// Writes a self-contained HTML page with the nodes that does not need any external resources.
+///
+/// The sunburst chart is sized by values of `chart_column`. Labels of top-level nodes are their names
+/// after `label_prefix` (e.g. [`KEYSPACE_LABEL_PREFIX`]), labels of other nodes start with the label of their top-level node.
+pub fn write_html<T: Tree, I: IntoIterator<Item = T>, W: Write>(nodes: I, columns: &[String], runs: &[RunInfo], chart_column: &str, label_prefix: &str, writer: &mut W) -> std::io::Result<()> {
+    let data = json!({
+        "columns": columns,
+        "chartColumn": chart_column,
+        "items": nodes.into_iter().map(|node| node_to_json(&node, label_prefix, None)).collect::<Vec<_>>(),
+        "runs": runs.iter().map(|run_info| json!({
+            "host": run_info.host,
+            "port": run_info.port,
+            "startedAt": format_timestamp(run_info.started_at),
+            "samples": run_info.samples.iter().map(|sample| json!({
+                "keyspace": sample.keyspace.to_string(),
+                "sampleSize": sample.sample_size,
+                "totalKeys": sample.total_keys,
+                "skipped": sample.skipped,
+            })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    });
+    // The data is embedded in a <script> tag, so it must not be able to close it.
+    let data = data.to_string().replace("</", "<\\/");
+    writer.write_all(TEMPLATE.replace(DATA_PLACEHOLDER, &data).as_bytes())
+}
+
+impl Results {
+    /// Writes a self-contained HTML page that does not need any external resources.
+    ///
+    /// The sunburst chart is sized by values of `chart_column`, top-level items are labelled by `label_prefix` and their names.
+    pub fn write_to_html<W: Write>(&self, runs: &[RunInfo], chart_column: &str, label_prefix: &str, writer: &mut W) -> std::io::Result<()> {
+        write_html(&self.items, &self.columns, runs, chart_column, label_prefix, writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use super::KEYSPACE_LABEL_PREFIX;
+    use crate::keyspace_info::KeyspaceId;
+    use crate::results::{Datum, Item, Results};
+    use crate::run_info::{RunInfo, SampleInfo};
+
+    #[test]
+    fn test_html_contains_data() {
+        let results = Results {
+            columns: vec!["count".to_string()],
+            items: vec![
+                Item {
+                    name: "</script>".to_string(),
+                    columns: {
+                        let mut map = HashMap::new();
+                        map.insert("count".to_string(), Datum::Count(3));
+                        map
+                    },
+                    children: vec![],
+                },
+            ],
+        };
+        let run_info = RunInfo {
+            host: "localhost".to_string(),
+            port: 6379,
+            started_at: SystemTime::now(),
+            samples: vec![SampleInfo { keyspace: KeyspaceId::new(0), sample_size: 3, total_keys: 3, skipped: 0 }],
+        };
+
+        let mut out = Vec::new();
+        results.write_to_html(&[run_info], "count", KEYSPACE_LABEL_PREFIX, &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("/*REPORT_DATA*/"));
+        assert!(html.contains(r#""host":"localhost""#));
+        assert!(html.contains(r#""name":"<\/script>""#));
+        assert_eq!(html.matches("</script>").count(), 2);
+    }
+
+    #[test]
+    fn test_nested_labels() {
+        let item = |name: &str, children| Item { name: name.to_string(), columns: HashMap::new(), children };
+        let results = Results {
+            columns: vec![],
+            items: vec![item("0", vec![item("foo:", vec![item("foo:1", vec![])])])],
+        };
+
+        let mut out = Vec::new();
+        results.write_to_html(&[], "count", KEYSPACE_LABEL_PREFIX, &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains(r#""label":"db0""#));
+        assert!(html.contains(r#""label":"db0 foo:""#));
+        assert!(html.contains(r#""label":"db0 foo:1""#));
+        assert!(!html.contains("foo:foo:1"));
+    }
+
+    #[test]
+    fn test_labels_without_keyspaces() {
+        let item = |name: &str| Item { name: name.to_string(), columns: HashMap::new(), children: vec![] };
+        let results = Results { columns: vec![], items: vec![item("1"), item("2")] };
+
+        let mut out = Vec::new();
+        results.write_to_html(&[], "count", "", &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains(r#""label":"1""#));
+        assert!(html.contains(r#""label":"2""#));
+        assert!(!html.contains(r#""label":"db"#));
+    }
+}
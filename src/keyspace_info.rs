@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::str::FromStr;
-use redis::{from_redis_value, FromRedisValue, RedisResult, Value};
+use redis::{from_redis_value, ErrorKind, FromRedisValue, RedisResult, Value};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct KeyspaceId(i64);
 
 impl KeyspaceId {
@@ -23,59 +25,142 @@ impl Display for KeyspaceId {
 }
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyspacesInfo {
     pub keyspaces: HashMap<KeyspaceId, KeyspaceInfo>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyspaceInfo {
     pub keys: u64,
     pub expires: u64,
     pub avg_ttl: u64,
+    /// Number of hash fields with expiration (Redis 7.4)
+    #[serde(default)]
+    pub subexpiry: u64,
+    /// Fields unknown to this version, e.g. from newer servers or forks
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub other: BTreeMap<String, String>,
+}
+
+/// Error of parsing `INFO keyspace`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseKeyspaceError(String);
+
+impl Display for ParseKeyspaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
+impl std::error::Error for ParseKeyspaceError {}
+
 impl KeyspaceInfo {
-    pub fn from_str(s: &str) -> KeyspaceInfo {
+    pub fn new(keys: u64, expires: u64, avg_ttl: u64) -> KeyspaceInfo {
+        KeyspaceInfo {
+            keys,
+            expires,
+            avg_ttl,
+            subexpiry: 0,
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl FromStr for KeyspaceInfo {
+    type Err = ParseKeyspaceError;
+
+    /// Parses fields of a keyspace like `keys=10,expires=2,avg_ttl=3000`, only `keys` is required.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |key: &str, value: &str| u64::from_str(value)
+            .map_err(|_| ParseKeyspaceError(format!("invalid value of {}: '{}'", key, value)));
         let mut keys = None;
-        let mut expires = None;
-        let mut avg_ttl = None;
-        for part in s.split(',') {
-            let mut kv = part.splitn(2, '=');
-            let key = kv.next().unwrap();
-            let value = kv.next().unwrap();
+        let mut info = KeyspaceInfo::new(0, 0, 0);
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=')
+                .ok_or_else(|| ParseKeyspaceError(format!("invalid keyspace field '{}'", part)))?;
             match key {
-                "keys" => keys = Some(u64::from_str(value).unwrap()),
-                "expires" => expires = Some(u64::from_str(value).unwrap()),
-                "avg_ttl" => avg_ttl = Some(u64::from_str(value).unwrap()),
-                _ => (),
+                "keys" => keys = Some(number(key, value)?),
+                "expires" => info.expires = number(key, value)?,
+                "avg_ttl" => info.avg_ttl = number(key, value)?,
+                "subexpiry" => info.subexpiry = number(key, value)?,
+                _ => { info.other.insert(key.to_string(), value.to_string()); },
             }
         }
-        KeyspaceInfo {
-            keys: keys.unwrap(),
-            expires: expires.unwrap(),
-            avg_ttl: avg_ttl.unwrap(),
-        }
+        info.keys = keys.ok_or_else(|| ParseKeyspaceError(format!("missing number of keys in '{}'", s)))?;
+        Ok(info)
     }
 }
 
-impl FromRedisValue for KeyspacesInfo {
-    fn from_redis_value(v: &Value) -> RedisResult<Self> {
-        let s: String = from_redis_value(v)?;
+impl FromStr for KeyspacesInfo {
+    type Err = ParseKeyspaceError;
+
+    /// Parses the `INFO keyspace` section, lines of other sections are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut keyspaces = HashMap::new();
-        for line in s.lines() {
-            if line.is_empty() || line == "# Keyspace" {
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
                 continue;
             }
-            let mut pair = line.splitn(2, ':');
-            let keyspace = pair.next().unwrap();
-            let (prefix, number) = keyspace.split_at(2);
-            assert_eq!(prefix, "db");
-            let number = KeyspaceId::new(i64::from_str(number).unwrap());
-            let info = pair.next().unwrap();
-            keyspaces.insert(number, KeyspaceInfo::from_str(info));
+            let number = match line.split_once(':') {
+                Some((name, info)) => name.strip_prefix("db").map(|number| (number, info)),
+                None => None,
+            };
+            let (number, info) = match number {
+                Some(number) => number,
+                None => continue,
+            };
+            let number = i64::from_str(number)
+                .map_err(|_| ParseKeyspaceError(format!("invalid keyspace in line '{}'", line)))?;
+            keyspaces.insert(KeyspaceId::new(number), KeyspaceInfo::from_str(info)?);
         }
         Ok(KeyspacesInfo { keyspaces })
     }
 }
 
+impl FromRedisValue for KeyspacesInfo {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let s: String = from_redis_value(v)?;
+        KeyspacesInfo::from_str(&s).map_err(|e| (ErrorKind::TypeError, "invalid INFO keyspace", e.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use super::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+
+    const REDIS_5: &str = "# Keyspace\r\ndb0:keys=12,expires=3,avg_ttl=51000\r\ndb5:keys=1,expires=0,avg_ttl=0\r\n";
+    const REDIS_7_4: &str = "# Keyspace\r\ndb0:keys=100,expires=10,avg_ttl=3600000,subexpiry=4\r\n";
+    const VALKEY_8: &str = "# Keyspace\r\ndb0:keys=7,expires=0,avg_ttl=0,subexpiry=0,future_field=abc\r\n";
+
+    fn keyspace(info: &str, db: i64) -> KeyspaceInfo {
+        KeyspacesInfo::from_str(info).unwrap().keyspaces[&KeyspaceId::new(db)].clone()
+    }
+
+    #[test]
+    fn test_versions() {
+        assert_eq!(keyspace(REDIS_5, 0), KeyspaceInfo::new(12, 3, 51000));
+        assert_eq!(keyspace(REDIS_5, 5), KeyspaceInfo::new(1, 0, 0));
+        assert_eq!(keyspace(REDIS_7_4, 0).subexpiry, 4);
+        let valkey = keyspace(VALKEY_8, 0);
+        assert_eq!(valkey.keys, 7);
+        assert_eq!(valkey.other["future_field"], "abc");
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(KeyspacesInfo::from_str("# Keyspace\r\n").unwrap().keyspaces.is_empty());
+        assert!(KeyspacesInfo::from_str("").unwrap().keyspaces.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(KeyspacesInfo::from_str("db0:expires=1").is_err());
+        assert!(KeyspacesInfo::from_str("db0:keys=abc").is_err());
+        assert!(KeyspacesInfo::from_str("dbx:keys=1").is_err());
+        assert!(KeyspacesInfo::from_str("db0:keys").is_err());
+        // Lines which do not describe keyspaces are skipped
+        assert!(KeyspacesInfo::from_str("cluster_slots:16384\r\ndb0:keys=1").is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+//! Estimates memory usage of keys of a Redis server per prefix by sampling.
+//!
+//! The analysis has two steps:
+//!
+//! 1. Collecting raw data into a [`Snapshot`]: [`sampling::sample`] samples keys of a running server,
+//!    [`rdb::snapshot_from_rdb`] reads all keys of an RDB file. Snapshots can be saved and loaded as JSON.
+//! 2. Building [`Results`] from the snapshot by [`analysis::build_results`]. Keys are grouped into a
+//!    [`PrefixMap`] by their prefixes (parts of names ending with `:`, `|`, `,`, `.` or `_`) and every
+//!    prefix becomes an [`Item`] with named columns.
+//!
+//! Results can be read programmatically or rendered as text ([`Results`] implements `Display`),
+//! CSV ([`Results::write_to_csv`]), HTML ([`Results::write_to_html`]) or browsed in [`tui::run`].
+//! The renderers are generic over [`Tree`], which is implemented by items of results and by nodes
+//! of a [`PrefixMap`] ([`results2::PrefixNode`]), so a tree can also be rendered without building [`Results`].
+//! Other sources of per-prefix results are AOF files ([`aof`]), `MONITOR` ([`monitor`]) and keyspace
+//! notifications ([`churn`], [`evictions`]).
+//!
+//! The library does not print anything, long-running functions report messages and progress to a [`Progress`].
+//!
+//! ```no_run
+//! use redis_analyzer::{build_results, sample, Datum, ResultsOptions, SamplingOptions, Silent};
+//!
+//! let snapshot = sample("127.0.0.1", 6379, &SamplingOptions::default(), &Silent)?;
+//! let results = build_results(&snapshot, &ResultsOptions::default());
+//! for keyspace in &results.items {
+//!     for prefix in &keyspace.children {
+//!         if let Some(Datum::Stat(memory)) = prefix.columns.get("estimated_total_memory_usage") {
+//!             println!("db{} {}: {:.0} bytes", keyspace.name, prefix.name, memory);
+//!         }
+//!     }
+//! }
+//! # Ok::<(), redis_analyzer::Error>(())
+//! ```
+
+pub mod error;
+pub mod keyspace_info;
+pub mod prefix_map;
+pub mod results;
+pub mod redis;
+pub mod results2;
+pub mod html;
+pub mod run_info;
+pub mod tui;
+pub mod snapshot;
+pub mod sampling;
+pub mod analysis;
+pub mod diff;
+pub mod rdb;
+mod memory_model;
+pub mod memory_info;
+pub mod aof;
+pub mod monitor;
+pub mod hotkeys;
+pub mod churn;
+pub mod evictions;
+pub mod exporter;
+pub mod watch;
+pub mod forecast;
+pub mod history;
+pub mod metrics;
+pub mod progress;
+
+pub use crate::analysis::{build_results, ResultsOptions};
+pub use crate::error::{Error, Result};
+pub use crate::prefix_map::PrefixMap;
+pub use crate::progress::{Progress, Silent};
+pub use crate::results::{Datum, Item, Results};
+pub use crate::results2::Tree;
+pub use crate::sampling::{sample, SamplingOptions};
+pub use crate::snapshot::Snapshot;
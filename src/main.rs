@@ -1,211 +1,572 @@
-mod keyspace_info;
-mod prefix_map;
-mod results;
-mod redis;
-mod results2;
-
-use std::collections::HashMap;
-use std::iter::Sum;
-use std::ops::Add;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use clap::{Args, Parser, Subcommand};
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::{Clear, ClearType};
 use indicatif::ProgressBar;
-use crate::keyspace_info::{KeyspaceId, KeyspacesInfo};
-use crate::prefix_map::PrefixMap;
-use crate::redis::RedisConnection;
-use crate::results::{Datum, Item, Results};
+use redis_analyzer::{analysis, aof, churn, diff, error, evictions, exporter, forecast, hotkeys, html, metrics, monitor, rdb, sampling, tui};
+use redis_analyzer::analysis::{build_results, ResultsOptions};
+use redis_analyzer::exporter::ExporterOptions;
+use redis_analyzer::forecast::InstanceForecast;
+use redis_analyzer::history::HistoryStore;
+use redis_analyzer::hotkeys::HotKeys;
+use redis_analyzer::keyspace_info::KeyspaceId;
+use redis_analyzer::memory_info::MemoryOverview;
+use redis_analyzer::metrics::{Collector, Ttl};
+use redis_analyzer::monitor::AccessStats;
+use redis_analyzer::prefix_map::escape_key;
+use redis_analyzer::progress::Progress;
+use redis_analyzer::results::Results;
+use redis_analyzer::run_info::{format_timestamp, RunInfo};
+use redis_analyzer::sampling::SamplingOptions;
+use redis_analyzer::snapshot::Snapshot;
+use redis_analyzer::tui::SampledKeys;
+use redis_analyzer::watch::{History, Run};
 
 #[derive(Parser, Debug)]
 #[clap()]
-struct Args {
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// Redis host
     #[clap(default_value = "127.0.0.1")]
     host: String,
     /// Redis port
     #[clap(default_value = "6379")]
     port: u16,
+    /// Save sampled data to a snapshot file, it can be rendered later by `report`
+    #[clap(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+    /// Append every analysis to a SQLite history database, it can be queried by `history`
+    #[clap(long, value_name = "FILE")]
+    history_db: Option<PathBuf>,
+    /// Sample only keys matching a glob-style pattern (e.g. 'orders:*'), estimates cover only matching keys
+    #[clap(long = "match", value_name = "PATTERN")]
+    pattern: Option<String>,
+    /// Sample only keys starting with this prefix, like `--match` with the escaped prefix followed by '*'
+    #[clap(long, conflicts_with = "pattern")]
+    prefix: Option<String>,
+    /// Sample only these databases (e.g. 0,3)
+    #[clap(long, value_name = "DB", use_delimiter = true)]
+    db: Vec<i64>,
+    /// Do not sample these databases
+    #[clap(long, value_name = "DB", use_delimiter = true)]
+    exclude_db: Vec<i64>,
+    /// Metrics collected for every sampled key: memory, type, ttl, cardinality, frequency (e.g. type,ttl). Memory is always collected
+    #[clap(long, value_name = "METRIC", use_delimiter = true, parse(try_from_str = metrics::collector))]
+    metrics: Vec<&'static dyn Collector>,
+    /// Sample the server repeatedly with this many seconds between runs and show trends of estimates
+    #[clap(long, value_name = "SECONDS", conflicts_with_all = &["csv", "html", "tui"])]
+    watch: Option<u64>,
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render results from a saved snapshot without connecting to Redis
+    Report {
+        /// Snapshot file created by `--save`
+        snapshot: PathBuf,
+    },
+    /// Compare two snapshots and show which prefixes grew
+    Diff {
+        /// Older snapshot
+        old: PathBuf,
+        /// Newer snapshot, current state of the server at HOST and PORT is used if not given
+        new: Option<PathBuf>,
+    },
+    /// Analyze an RDB file instead of a running server, all keys are analyzed
+    Rdb {
+        /// RDB file (e.g. dump.rdb)
+        file: PathBuf,
+    },
+    /// Analyze an AOF, shows sizes of keys and number of writes per prefix
+    Aof {
+        /// AOF file, manifest of a multi-part AOF or directory with the manifest (e.g. appendonlydir)
+        path: PathBuf,
+    },
+    /// Analyze accesses to keys captured by MONITOR, shows reads and writes per prefix
+    Monitor {
+        /// How long to monitor the server at HOST and PORT, in seconds
+        #[clap(long, default_value = "10")]
+        duration: u64,
+        /// Read saved MONITOR output (e.g. from `redis-cli monitor`) instead of connecting to the server
+        #[clap(long, value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+    /// Find the most accessed keys and prefixes, uses LFU counters or MONITOR if they are not available
+    Hotkeys {
+        /// How long to monitor the server when LFU counters are not available, in seconds
+        #[clap(long, default_value = "10")]
+        duration: u64,
+    },
+    /// Collect keyspace notifications and show how many keys of each prefix are set, deleted, expired or evicted
+    Churn {
+        /// How long to collect notifications, in seconds, Ctrl-C stops earlier
+        #[clap(long, default_value = "60")]
+        duration: u64,
+        /// Enable missing classes of `notify-keyspace-events` during the capture, the previous value is restored afterwards
+        #[clap(long)]
+        enable_notifications: bool,
+    },
+    /// Attribute expired and evicted keys to prefixes by keyspace notifications, compared with the counters of `INFO stats`
+    Evictions {
+        /// How long to collect notifications, in seconds, Ctrl-C stops earlier
+        #[clap(long, default_value = "60")]
+        duration: u64,
+        /// Enable expired and evicted notifications during the capture, the previous value is restored afterwards
+        #[clap(long)]
+        enable_notifications: bool,
+    },
+    /// Fit growth trends of prefixes to several snapshots and estimate when the server reaches maxmemory
+    Forecast {
+        /// Snapshots created by `--save` at different times
+        #[clap(required = true, min_values = 2)]
+        snapshots: Vec<PathBuf>,
+    },
+    /// Serve per-prefix estimates as Prometheus metrics, the server is analyzed periodically in the background
+    Exporter {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:9187")]
+        listen: String,
+        /// Time between analyses, in seconds
+        #[clap(long, default_value = "300")]
+        interval: u64,
+    },
+    /// Query analyses stored by `--history-db`
+    History {
+        #[clap(subcommand)]
+        query: HistoryQuery,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryQuery {
+    /// List stored runs
+    Runs,
+    /// Show estimates of a prefix in all runs
+    Prefix {
+        /// Keyspace of the prefix (e.g. 0)
+        keyspace: String,
+        /// Prefix as listed in the results (e.g. 'user:'), the whole keyspace if empty
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+    /// Compare estimates of two runs, prefixes that grew the most are listed first
+    Compare {
+        /// Id of the older run
+        old: i64,
+        /// Id of the newer run
+        new: i64,
+    },
+}
+
+#[derive(Args, Debug)]
+struct OutputArgs {
     /// Output CSV
-    #[clap(long)]
+    #[clap(long, global = true)]
     csv: bool,
+    /// Output self-contained HTML report
+    #[clap(long, global = true, conflicts_with = "csv")]
+    html: bool,
+    /// Browse results in interactive terminal UI
+    #[clap(long, global = true, conflicts_with_all = &["csv", "html"])]
+    tui: bool,
+    /// List children of prefixes with at least this many sampled keys
+    #[clap(long, global = true, default_value = "3")]
+    min_count: u64,
+    /// Maximum depth of listed prefixes (keyspaces are at depth 1)
+    #[clap(long, global = true)]
+    max_depth: Option<usize>,
+    /// Estimate memory saved if names of prefixes were shortened to this many bytes
+    #[clap(long, global = true, value_name = "BYTES")]
+    shorten_prefixes_to: Option<usize>,
 }
 
-#[derive(Debug, Clone, Copy)]
-struct KeyspaceTreeNodeInfo {
-    memory_usage: u64,
-    count: u64,
-}
-impl Default for KeyspaceTreeNodeInfo {
-    fn default() -> Self {
-        KeyspaceTreeNodeInfo {
-            memory_usage: 0,
-            count: 0,
+impl OutputArgs {
+    fn results_options(&self) -> ResultsOptions {
+        ResultsOptions {
+            min_count: self.min_count,
+            max_depth: self.max_depth,
+            shorten_prefixes_to: self.shorten_prefixes_to,
+            metrics: Vec::new(),
         }
     }
 }
-impl Add for KeyspaceTreeNodeInfo {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        Self {
-            memory_usage: self.memory_usage + other.memory_usage,
-            count: self.count + other.count,
+
+/// Prints messages of the library to stderr and draws its progress as a progress bar or a spinner.
+struct ConsoleProgress {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl Progress for ConsoleProgress {
+    fn message(&self, message: &str) {
+        match &*self.bar.lock().unwrap() {
+            Some(bar) => bar.println(message),
+            None => eprintln!("{}", message),
         }
     }
-}
 
-impl Sum for KeyspaceTreeNodeInfo {
-    fn sum<I>(iter: I) -> Self
-    where
-        I: Iterator<Item = Self>,
-    {
-        iter.fold(Default::default(), |acc, x| acc + x)
+    fn start(&self, total: Option<u64>) {
+        *self.bar.lock().unwrap() = Some(total.map_or_else(ProgressBar::new_spinner, ProgressBar::new));
+    }
+
+    fn inc(&self, delta: u64) {
+        if let Some(bar) = &*self.bar.lock().unwrap() {
+            bar.inc(delta);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_and_clear();
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct ExtendedKeyspaceTreeNodeInfo {
-    info: KeyspaceTreeNodeInfo,
-    estimated_total_count: f64,
-    estimated_total_memory_usage: f64,
+static CONSOLE: ConsoleProgress = ConsoleProgress { bar: Mutex::new(None) };
+
+fn main() {
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Report { snapshot }) => {
+            let snapshot = load_snapshot(snapshot);
+            let results = build_results(&snapshot, &cli.results_options());
+            output_snapshots(&results, &[&snapshot], analysis::CHART_COLUMN, &cli.output);
+        },
+        Some(Command::Diff { old, new }) => {
+            let old = load_snapshot(old);
+            let new = match new {
+                Some(new) => load_snapshot(new),
+                None => sample(&cli),
+            };
+            let results = diff::build_diff(&old, &new, &cli.results_options());
+            output_snapshots(&results, &[&old, &new], diff::CHART_COLUMN, &cli.output);
+        },
+        Some(Command::Rdb { file }) => {
+            let snapshot = match rdb::snapshot_from_rdb(file) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    eprintln!("Reading RDB file {} failed: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            save_snapshot(&cli, &snapshot);
+            let results = build_results(&snapshot, &cli.results_options());
+            output_snapshots(&results, &[&snapshot], analysis::CHART_COLUMN, &cli.output);
+        },
+        Some(Command::Aof { path }) => {
+            let analysis = match aof::read_aof(path, &CONSOLE) {
+                Ok(analysis) => analysis,
+                Err(e) => {
+                    eprintln!("Reading AOF {} failed: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            };
+            if analysis.truncated {
+                eprintln!("The AOF ends with an incomplete command, it was ignored");
+            }
+            eprintln!("Replayed {} commands", analysis.commands);
+            let results = aof::build_results(&analysis, &cli.results_options());
+            output(&results, &[analysis.run_info(path)], &analysis.sampled_keys(), aof::CHART_COLUMN, html::KEYSPACE_LABEL_PREFIX, &cli.output);
+        },
+        Some(Command::Monitor { duration, file }) => {
+            let (stats, source) = match file {
+                Some(file) => match monitor::read_capture(file) {
+                    Ok(stats) => (stats, (file.display().to_string(), 0)),
+                    Err(e) => {
+                        eprintln!("Reading capture {} failed: {}", file.display(), e);
+                        std::process::exit(1);
+                    }
+                },
+                None => match monitor::capture(&cli.host, cli.port, std::time::Duration::from_secs(*duration), &CONSOLE) {
+                    Ok(stats) => (stats, (cli.host.clone(), cli.port)),
+                    Err(e) => {
+                        eprintln!("Redis query failed: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+            };
+            let (host, port) = source;
+            output_access_stats(&stats, host, port, &cli.output);
+        },
+        Some(Command::Hotkeys { duration }) => {
+            match hotkeys::find_hot_keys(&cli.host, cli.port, cli.sampling_options(), std::time::Duration::from_secs(*duration), &CONSOLE) {
+                Ok(HotKeys::Lfu(snapshot)) => {
+                    save_snapshot(&cli, &snapshot);
+                    let mut results = build_results(&snapshot, &cli.results_options());
+                    results.sort_by_column(analysis::FREQUENCY_COLUMN);
+                    output_snapshots(&results, &[&*snapshot], analysis::FREQUENCY_COLUMN, &cli.output);
+                },
+                Ok(HotKeys::Monitor(stats)) => output_access_stats(&stats, cli.host.clone(), cli.port, &cli.output),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some(Command::Churn { duration, enable_notifications }) => {
+            let stats = match churn::capture(&cli.host, cli.port, std::time::Duration::from_secs(*duration), *enable_notifications, churn::ALL_EVENTS, &CONSOLE) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            eprintln!("Received {} events in {:.1} seconds", stats.events, stats.duration);
+            let results = churn::build_results(&stats, &cli.results_options());
+            output(&results, &[stats.run_info(cli.host.clone(), cli.port)], &stats.sampled_keys(), churn::CHART_COLUMN, html::KEYSPACE_LABEL_PREFIX, &cli.output);
+        },
+        Some(Command::Evictions { duration, enable_notifications }) => {
+            let stats = match evictions::capture(&cli.host, cli.port, std::time::Duration::from_secs(*duration), *enable_notifications, &CONSOLE) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let mut results = evictions::build_results(&stats, &cli.results_options());
+            results.sort_by_column(evictions::CHART_COLUMN);
+            if !(cli.output.csv || cli.output.html || cli.output.tui) {
+                println!("{}\n", stats);
+            }
+            let events = &stats.events;
+            output(&results, &[events.run_info(cli.host.clone(), cli.port)], &events.sampled_keys(), evictions::CHART_COLUMN, html::KEYSPACE_LABEL_PREFIX, &cli.output);
+        },
+        Some(Command::Forecast { snapshots }) => {
+            let mut snapshots = snapshots.iter().map(|path| load_snapshot(path)).collect::<Vec<_>>();
+            snapshots.sort_by_key(|snapshot| snapshot.created_at);
+            let runs = snapshots.iter()
+                .map(|snapshot| Run::from_snapshot(snapshot, &build_results(snapshot, &cli.results_options())))
+                .collect::<Vec<_>>();
+            let latest = snapshots.last().unwrap();
+            let mut results = build_results(latest, &cli.results_options());
+            forecast::add_forecast_columns(&runs, &mut results);
+            results.sort_by_column(forecast::TREND_COLUMN);
+            print_forecast(&runs, latest, &cli.output);
+            output(&results, &snapshots.iter().map(Snapshot::run_info).collect::<Vec<_>>(), &sampled_keys(latest), forecast::TREND_COLUMN, html::KEYSPACE_LABEL_PREFIX, &cli.output);
+        },
+        Some(Command::Exporter { listen, interval }) => {
+            let options = ExporterOptions {
+                host: cli.host.clone(),
+                port: cli.port,
+                listen: listen.clone(),
+                interval: std::time::Duration::from_secs(*interval),
+                sampling: cli.sampling_options().with_metric(&Ttl),
+                // Every exported prefix is a time series, so deep prefixes are left out by default
+                results: ResultsOptions { max_depth: cli.output.max_depth.or(Some(3)), ..cli.results_options() },
+            };
+            if let Err(e) = exporter::run(options, &CONSOLE) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::History { query }) => {
+            let store = open_history(&cli);
+            // Runs and series of a prefix are listed by ids of runs, not by keyspaces
+            let (results, chart_column, label_prefix) = match query {
+                HistoryQuery::Runs => (store.runs_results().map(Some), analysis::CHART_COLUMN, "run "),
+                HistoryQuery::Prefix { keyspace, prefix } => (store.prefix_results(keyspace, prefix).map(Some), analysis::CHART_COLUMN, "run "),
+                HistoryQuery::Compare { old, new } => (store.compare(*old, *new), diff::CHART_COLUMN, html::KEYSPACE_LABEL_PREFIX),
+            };
+            match results {
+                Ok(Some(results)) => output(&results, &[], &SampledKeys::new(), chart_column, label_prefix, &cli.output),
+                Ok(None) => {
+                    eprintln!("No such run in the history, list runs by `history runs`");
+                    std::process::exit(1);
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                },
+            }
+        },
+        None => match cli.watch {
+            Some(interval) => watch(&cli, std::time::Duration::from_secs(interval)),
+            None => {
+                let snapshot = sample(&cli);
+                let results = build_results(&snapshot, &cli.results_options());
+                output_snapshots(&results, &[&snapshot], analysis::CHART_COLUMN, &cli.output);
+            },
+        },
+    }
 }
-impl Default for ExtendedKeyspaceTreeNodeInfo {
-    fn default() -> Self {
-        ExtendedKeyspaceTreeNodeInfo {
-            info: Default::default(),
-            estimated_total_count: 0.0,
-            estimated_total_memory_usage: 0.0,
+
+fn load_snapshot(path: &Path) -> Snapshot {
+    match Snapshot::load(path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("Loading snapshot {} failed: {}", path.display(), e);
+            std::process::exit(1);
         }
     }
 }
-impl Add for ExtendedKeyspaceTreeNodeInfo {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        Self {
-            info: self.info + other.info,
-            estimated_total_count: self.estimated_total_count + other.estimated_total_count,
-            estimated_total_memory_usage: self.estimated_total_memory_usage + other.estimated_total_memory_usage,
+
+impl Cli {
+    /// Options of the results with the columns of metrics in the order of `--metrics`.
+    fn results_options(&self) -> ResultsOptions {
+        ResultsOptions { metrics: self.metrics.clone(), ..self.output.results_options() }
+    }
+
+    fn sampling_options(&self) -> SamplingOptions {
+        SamplingOptions {
+            pattern: match (&self.pattern, &self.prefix) {
+                (Some(pattern), _) => Some(pattern.clone()),
+                (None, Some(prefix)) => Some(format!("{}*", sampling::escape_pattern(prefix))),
+                (None, None) => None,
+            },
+            databases: self.db.iter().copied().map(KeyspaceId::new).collect(),
+            excluded_databases: self.exclude_db.iter().copied().map(KeyspaceId::new).collect(),
+            metrics: self.metrics.clone(),
         }
     }
 }
 
-impl Sum for ExtendedKeyspaceTreeNodeInfo {
-    fn sum<I>(iter: I) -> Self
-        where
-            I: Iterator<Item = Self>,
-    {
-        iter.fold(Default::default(), |acc, x| acc + x)
+/// Samples the server and saves the snapshot if requested.
+fn sample(cli: &Cli) -> Snapshot {
+    let snapshot = match sampling::sample(&cli.host, cli.port, &cli.sampling_options(), &CONSOLE) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    save_snapshot(cli, &snapshot);
+    snapshot
+}
+
+/// Samples the server every `interval` and prints the results with trends until interrupted.
+fn watch(cli: &Cli, interval: std::time::Duration) -> ! {
+    let mut history = History::default();
+    loop {
+        let started = std::time::Instant::now();
+        // A failed run does not stop watching, the server may be only temporarily unavailable
+        let snapshot = match sampling::sample(&cli.host, cli.port, &cli.sampling_options(), &CONSOLE) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::thread::sleep(interval.saturating_sub(started.elapsed()));
+                continue;
+            }
+        };
+        save_snapshot(cli, &snapshot);
+        let mut results = build_results(&snapshot, &cli.results_options());
+        history.add(Run::from_snapshot(&snapshot, &results));
+        history.add_trend_columns(&mut results);
+        forecast::add_forecast_columns(history.runs(), &mut results);
+        let mut out = std::io::stdout();
+        let _ = crossterm::execute!(out, Clear(ClearType::All), MoveTo(0, 0));
+        println!("{} runs, last at {}", history.runs().len(), format_timestamp(snapshot.run_info().started_at));
+        print_forecast(history.runs(), &snapshot, &cli.output);
+        output_snapshots(&results, &[&snapshot], analysis::CHART_COLUMN, &cli.output);
+        std::thread::sleep(interval.saturating_sub(started.elapsed()));
     }
 }
 
+fn save_snapshot(cli: &Cli, snapshot: &Snapshot) {
+    if let Some(path) = &cli.save {
+        if let Err(e) = snapshot.save(path) {
+            eprintln!("Saving snapshot {} failed: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        eprintln!("Snapshot saved to {}", path.display());
+    }
+    if cli.history_db.is_some() {
+        let results = build_results(snapshot, &cli.results_options());
+        match open_history(cli).add_run(snapshot, &results) {
+            Ok(run_id) => eprintln!("Stored as run {} in the history", run_id),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
 
-fn main() {
-    let args = Args::parse();
-    let mut connection = RedisConnection::open(args.host.clone(), args.port, KeyspaceId::new(0)).unwrap();
-    let keyspaces = match connection.keyspaces() {
-        Ok(keyspaces) => keyspaces,
-        Err(e) => {
-            eprintln!("Redis query failed: {}", e);
+/// Opens the history database given by `--history-db`.
+fn open_history(cli: &Cli) -> HistoryStore {
+    let path = match &cli.history_db {
+        Some(path) => path,
+        None => {
+            eprintln!("The history database is not set, use --history-db <FILE>");
             std::process::exit(1);
         }
     };
-    eprintln!("Found {} keyspaces", keyspaces.keyspaces.len());
-
-    let sample_sizes = keyspaces.keyspaces.iter().map(|k| (*k.0, match k.1.keys {
-        0..=100 => k.1.keys,
-        101..=500 => k.1.keys / 5,
-        501..=1000 => k.1.keys / 10,
-        _ => 200,
-    })).collect::<HashMap<_, _>>();
-
-    let samples: HashMap<_, _> = sample_sizes.iter().map(|(&keyspace, &sample_size)| {
-        let keyspace_info = keyspaces.keyspaces[&keyspace].clone();
-        let total = keyspace_info.keys;
-        eprintln!("Getting sample from db{keyspace} - {sample_size} keys of {total} total");
-        let mut prefix_map = PrefixMap::default();
-        let mut connection = RedisConnection::open(args.host.clone(), args.port, keyspace).unwrap();
-        let result = connection.scan(sample_size).unwrap();
-        let bar = ProgressBar::new(result.len() as u64);
-        for key in result {
-            let memory_usage = connection.memory_usage(&key).unwrap();
-            prefix_map.insert(key, memory_usage);
-            bar.inc(1);
+    match HistoryStore::open(path) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Opening history database {} failed: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the forecast of memory of the whole server in the text output.
+fn print_forecast(runs: &[Run], latest: &Snapshot, args: &OutputArgs) {
+    if args.csv || args.html || args.tui {
+        return;
+    }
+    match InstanceForecast::new(runs, latest.memory_limit()) {
+        Some(forecast) => println!("Forecast: {}\n", forecast),
+        None => println!("Forecast: used_memory of the server is not known at two different times\n"),
+    }
+}
+
+/// Outputs accesses captured by `MONITOR` in the requested format.
+fn output_access_stats(stats: &AccessStats, host: String, port: u16, args: &OutputArgs) {
+    eprintln!("Analyzed {} commands, {} of them without keys", stats.commands, stats.keyless_commands);
+    let results = monitor::build_results(stats, &args.results_options());
+    output(&results, &[stats.run_info(host, port)], &stats.sampled_keys(), monitor::CHART_COLUMN, html::KEYSPACE_LABEL_PREFIX, args);
+}
+
+/// Outputs results of snapshots in the requested format, `snapshots` are the sources of the results.
+fn output_snapshots(results: &Results, snapshots: &[&Snapshot], chart_column: &str, args: &OutputArgs) {
+    let runs = snapshots.iter().map(|snapshot| snapshot.run_info()).collect::<Vec<_>>();
+    let latest = snapshots.last().unwrap();
+    let skipped = latest.skipped();
+    if !skipped.is_empty() {
+        eprintln!("{}, estimates are based on the remaining keys", skipped);
+    }
+    if let ([snapshot], Some(memory_info)) = (snapshots, &latest.memory_info) {
+        if !(args.csv || args.html || args.tui) {
+            let overview = MemoryOverview {
+                info: memory_info,
+                stats: snapshot.memory_stats.as_ref(),
+                estimated_keys: snapshot.estimated_memory_usage(),
+            };
+            println!("{}", overview);
         }
-        (keyspace, (sample_size, keyspace_info, prefix_map))
-    }).collect();
+    }
+    output(results, &runs, &sampled_keys(latest), chart_column, html::KEYSPACE_LABEL_PREFIX, args);
+}
 
-    let with_info = samples.iter().map(|(keyspace, (sample_size, keyspace_info, prefix_map))| {
-        eprintln!("Analyzing db{keyspace}");
-        let analyzed_share = *sample_size as f64 / keyspace_info.keys as f64;
+/// Sampled keys of the snapshot for the terminal UI.
+fn sampled_keys(snapshot: &Snapshot) -> SampledKeys {
+    snapshot.samples.iter().map(|sample| {
         (
-            keyspace,
-            prefix_map.simplify().transform_to_prefix_map::<ExtendedKeyspaceTreeNodeInfo, _>(&|_key, value, children| {
-                let mut out_value = children.iter().map(|(_, map)| map.value).sum::<ExtendedKeyspaceTreeNodeInfo>();
-                if value.is_some() {
-                    out_value.info.count += 1;
-                    out_value.info.memory_usage += value.unwrap();
-                }
-                (
-                    ExtendedKeyspaceTreeNodeInfo {
-                        info: out_value.info,
-                        estimated_total_memory_usage: out_value.info.memory_usage as f64 / analyzed_share,
-                        estimated_total_count: out_value.info.count as f64 / analyzed_share,
-                    },
-                    children,
-                )
-            })
+            sample.keyspace.to_string(),
+            sample.keys.iter().map(|key| (escape_key(&key.key), key.memory_usage)).collect(),
         )
-    }).collect::<HashMap<_, _>>();
-
-
-    let merged = PrefixMap::new(
-        with_info.iter().map(|(_, it)| it.value).sum::<ExtendedKeyspaceTreeNodeInfo>(),
-        with_info.into_iter().map(|(keyspace, map)| (keyspace.to_string(), map)).collect(),
-    );
-
-    let results = Results {
-        columns: vec![
-            "count".to_string(),
-            "count_percent".to_string(),
-            "memory_usage".to_string(),
-            "memory_usage_percent".to_string(),
-            "avg_memory_usage".to_string(),
-            "estimated_total_count".to_string(),
-            "estimated_total_memory_usage".to_string(),
-        ],
-        items: merged.transform::<(ExtendedKeyspaceTreeNodeInfo, Vec<Item>), _>(&|parent_key, value, children| {
-            let mut children = children.into_iter().collect::<Vec<_>>();
-            children.sort_by(|(_, (info_l, _)), (_, (info_r, _))| info_l.estimated_total_memory_usage.partial_cmp(&info_r.estimated_total_memory_usage).unwrap());
-            let total = children.iter().map(|(_, (count, _))| *count).sum::<ExtendedKeyspaceTreeNodeInfo>();
-            (
-                value.clone(),
-                children
-                    .into_iter()
-                    .rev()
-                    .map(|(key, (info, children))| {
-                        Item {
-                            name: key[parent_key.len()..].to_string(),
-                            columns: {
-                                let mut map = HashMap::new();
-                                map.insert("count".to_string(), Datum::Count(info.info.count as i64));
-                                map.insert("count_percent".to_string(), Datum::Percent(info.info.count as f64 / total.info.count as f64));
-                                map.insert("memory_usage".to_string(), Datum::Count(info.info.memory_usage as i64));
-                                map.insert("memory_usage_percent".to_string(), Datum::Percent(info.info.memory_usage as f64 / total.info.memory_usage as f64));
-                                map.insert("avg_memory_usage".to_string(), Datum::Stat(info.info.memory_usage as f64 / info.info.count as f64));
-                                map.insert("estimated_total_count".to_string(), Datum::Stat(info.estimated_total_count));
-                                map.insert("estimated_total_memory_usage".to_string(), Datum::Stat(info.estimated_total_memory_usage));
-                                map
-                            },
-                            children: if info.info.count > 2 { children } else { vec![] },
-                        }
-                    })
-                    .collect::<Vec<_>>(),
-            )
-        }).1,
-    };
+    }).collect()
+}
 
-    if args.csv {
+/// Outputs results in the requested format, `runs` describe the sources of the results.
+/// Top-level items are labelled by `label_prefix` and their names in the HTML report.
+fn output(results: &Results, runs: &[RunInfo], sampled_keys: &SampledKeys, chart_column: &str, label_prefix: &str, args: &OutputArgs) {
+    let result: error::Result<()> = if args.csv {
         let mut writer = csv::Writer::from_writer(std::io::stdout());
-        results.write_to_csv(&mut writer).unwrap();
+        results.write_to_csv(&mut writer).map_err(|e| error::Error::Io(e.into()))
+    } else if args.html {
+        results.write_to_html(runs, chart_column, label_prefix, &mut std::io::stdout()).map_err(Into::into)
+    } else if args.tui {
+        tui::run(results, sampled_keys).map_err(Into::into)
     } else {
         println!("{}", results);
+        Ok(())
+    };
+    if let Err(e) = result {
+        eprintln!("Writing output failed: {}", e);
+        std::process::exit(1);
     }
-
 }
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use redis::{from_redis_value, ErrorKind, FromRedisValue, RedisResult, Value};
+use serde::{Deserialize, Serialize};
+use crate::keyspace_info::KeyspaceId;
+
+/// Fields of `INFO memory`, fields missing in older versions are zero.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryInfo {
+    pub used_memory: u64,
+    pub used_memory_rss: u64,
+    pub used_memory_peak: u64,
+    pub used_memory_overhead: u64,
+    pub used_memory_startup: u64,
+    pub used_memory_dataset: u64,
+    pub used_memory_scripts: u64,
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    pub mem_fragmentation_ratio: f64,
+    pub mem_replication_backlog: u64,
+    pub mem_clients_slaves: u64,
+    pub mem_clients_normal: u64,
+    pub mem_aof_buffer: u64,
+    pub mem_allocator: String,
+}
+
+impl MemoryInfo {
+    pub fn from_fields(fields: &BTreeMap<String, String>) -> MemoryInfo {
+        let number = |name: &str| fields.get(name).and_then(|value| u64::from_str(value).ok()).unwrap_or(0);
+        let text = |name: &str| fields.get(name).cloned().unwrap_or_default();
+        MemoryInfo {
+            used_memory: number("used_memory"),
+            used_memory_rss: number("used_memory_rss"),
+            used_memory_peak: number("used_memory_peak"),
+            used_memory_overhead: number("used_memory_overhead"),
+            used_memory_startup: number("used_memory_startup"),
+            used_memory_dataset: number("used_memory_dataset"),
+            used_memory_scripts: number("used_memory_scripts"),
+            maxmemory: number("maxmemory"),
+            maxmemory_policy: text("maxmemory_policy"),
+            mem_fragmentation_ratio: fields.get("mem_fragmentation_ratio").and_then(|value| f64::from_str(value).ok()).unwrap_or(0.0),
+            mem_replication_backlog: number("mem_replication_backlog"),
+            mem_clients_slaves: number("mem_clients_slaves"),
+            mem_clients_normal: number("mem_clients_normal"),
+            mem_aof_buffer: number("mem_aof_buffer"),
+            mem_allocator: text("mem_allocator"),
+        }
+    }
+}
+
+impl FromRedisValue for MemoryInfo {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let s: String = from_redis_value(v)?;
+        let fields = s.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+            .collect();
+        Ok(MemoryInfo::from_fields(&fields))
+    }
+}
+
+/// Overhead of hash tables of a keyspace from `MEMORY STATS`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyspaceOverhead {
+    pub hashtable_main: u64,
+    pub hashtable_expires: u64,
+}
+
+/// Reply of `MEMORY STATS`, fields missing in older versions are zero.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub peak_allocated: u64,
+    pub total_allocated: u64,
+    pub startup_allocated: u64,
+    pub replication_backlog: u64,
+    pub clients_slaves: u64,
+    pub clients_normal: u64,
+    pub cluster_links: u64,
+    pub aof_buffer: u64,
+    pub lua_caches: u64,
+    pub functions_caches: u64,
+    pub overhead_total: u64,
+    pub keys_count: u64,
+    pub dataset_bytes: u64,
+    pub fragmentation: f64,
+    pub keyspaces: HashMap<KeyspaceId, KeyspaceOverhead>,
+}
+
+/// Converts a number in a `MEMORY STATS` reply, doubles are bulk strings in RESP2.
+fn stats_number(v: &Value) -> RedisResult<f64> {
+    match v {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Data(_) | Value::Status(_) => {
+            let s: String = from_redis_value(v)?;
+            f64::from_str(&s).map_err(|_| (ErrorKind::TypeError, "invalid number in MEMORY STATS", s).into())
+        },
+        _ => Err((ErrorKind::TypeError, "unexpected value in MEMORY STATS").into()),
+    }
+}
+
+/// Pairs of a flat name-value array.
+fn stats_pairs(v: &Value) -> RedisResult<Vec<(String, &Value)>> {
+    match v {
+        Value::Bulk(items) => items.chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| Ok((from_redis_value::<String>(&pair[0])?, &pair[1])))
+            .collect(),
+        _ => Err((ErrorKind::TypeError, "MEMORY STATS reply is not an array").into()),
+    }
+}
+
+impl FromRedisValue for MemoryStats {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let mut stats = MemoryStats::default();
+        for (name, value) in stats_pairs(v)? {
+            if let Some(db) = name.strip_prefix("db.") {
+                let keyspace = KeyspaceId::new(i64::from_str(db).map_err(|_| (ErrorKind::TypeError, "invalid database in MEMORY STATS"))?);
+                let mut overhead = KeyspaceOverhead::default();
+                for (name, value) in stats_pairs(value)? {
+                    match name.as_str() {
+                        "overhead.hashtable.main" => overhead.hashtable_main = stats_number(value)? as u64,
+                        "overhead.hashtable.expires" => overhead.hashtable_expires = stats_number(value)? as u64,
+                        _ => {},
+                    }
+                }
+                stats.keyspaces.insert(keyspace, overhead);
+                continue;
+            }
+            let field = match name.as_str() {
+                "peak.allocated" => &mut stats.peak_allocated,
+                "total.allocated" => &mut stats.total_allocated,
+                "startup.allocated" => &mut stats.startup_allocated,
+                "replication.backlog" => &mut stats.replication_backlog,
+                "clients.slaves" => &mut stats.clients_slaves,
+                "clients.normal" => &mut stats.clients_normal,
+                "cluster.links" => &mut stats.cluster_links,
+                "aof.buffer" => &mut stats.aof_buffer,
+                "lua.caches" => &mut stats.lua_caches,
+                "functions.caches" => &mut stats.functions_caches,
+                "overhead.total" => &mut stats.overhead_total,
+                "keys.count" => &mut stats.keys_count,
+                "dataset.bytes" => &mut stats.dataset_bytes,
+                "fragmentation" => {
+                    stats.fragmentation = stats_number(value)?;
+                    continue;
+                },
+                // Derived values (percentages, bytes per key) and unknown fields of newer versions
+                _ => continue,
+            };
+            *field = stats_number(value)? as u64;
+        }
+        Ok(stats)
+    }
+}
+
+/// Reconciles memory reported by the server with estimated memory of keys.
+pub struct MemoryOverview<'a> {
+    pub info: &'a MemoryInfo,
+    pub stats: Option<&'a MemoryStats>,
+    /// Sum of estimated memory usage of all keys
+    pub estimated_keys: f64,
+}
+
+impl MemoryOverview<'_> {
+    /// Parts of used memory which are not data of keys.
+    fn overhead(&self) -> Vec<(&'static str, u64)> {
+        let info = self.info;
+        let mut parts = vec![
+            ("startup", info.used_memory_startup),
+            ("replication backlog", info.mem_replication_backlog),
+            ("replica client buffers", info.mem_clients_slaves),
+            ("client buffers", info.mem_clients_normal),
+            ("AOF buffer", info.mem_aof_buffer),
+            ("scripts", info.used_memory_scripts),
+        ];
+        if let Some(stats) = self.stats {
+            parts.push(("cluster links", stats.cluster_links));
+            parts.push(("keyspace hash tables", stats.keyspaces.values().map(|o| o.hashtable_main + o.hashtable_expires).sum()));
+        }
+        let known = parts.iter().map(|(_, bytes)| bytes).sum::<u64>();
+        parts.push(("other overhead", info.used_memory_overhead.saturating_sub(known)));
+        parts
+    }
+}
+
+impl Display for MemoryOverview<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let info = self.info;
+        let total = info.used_memory.max(1) as f64;
+        let line = |f: &mut Formatter<'_>, label: &str, bytes: f64| {
+            writeln!(f, "  {:<28} {:>16.0} {:>6.1}%", label, bytes, bytes / total * 100.0)
+        };
+        writeln!(f, "Memory ({} allocator, maxmemory {}, policy {})", info.mem_allocator, info.maxmemory, info.maxmemory_policy)?;
+        line(f, "used memory", info.used_memory as f64)?;
+        for (label, bytes) in self.overhead() {
+            line(f, &format!("  {}", label), bytes as f64)?;
+        }
+        line(f, "  dataset", info.used_memory_dataset as f64)?;
+        line(f, "    estimated keys", self.estimated_keys)?;
+        line(f, "    not explained by keys", info.used_memory_dataset as f64 - self.estimated_keys)?;
+        line(f, "fragmentation", info.used_memory_rss as f64 - info.used_memory as f64)?;
+        line(f, "RSS", info.used_memory_rss as f64)?;
+        writeln!(f, "  fragmentation ratio {:.2}, peak used memory {}", info.mem_fragmentation_ratio, info.used_memory_peak)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use redis::{FromRedisValue, Value};
+    use crate::keyspace_info::KeyspaceId;
+    use super::{MemoryInfo, MemoryOverview, MemoryStats};
+
+    const INFO_MEMORY: &str = "# Memory\r\nused_memory:1000000\r\nused_memory_human:976.56K\r\nused_memory_rss:1500000\r\n\
+        used_memory_peak:1200000\r\nused_memory_overhead:400000\r\nused_memory_startup:300000\r\n\
+        used_memory_dataset:600000\r\nused_memory_scripts:1000\r\nmaxmemory:0\r\nmaxmemory_policy:noeviction\r\n\
+        mem_fragmentation_ratio:1.50\r\nmem_replication_backlog:0\r\nmem_clients_slaves:0\r\n\
+        mem_clients_normal:20000\r\nmem_aof_buffer:0\r\nmem_allocator:jemalloc-5.3.0\r\n";
+
+    fn data(s: &str) -> Value {
+        Value::Data(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_info_memory() {
+        let info = MemoryInfo::from_redis_value(&data(INFO_MEMORY)).unwrap();
+        assert_eq!(info.used_memory, 1000000);
+        assert_eq!(info.mem_fragmentation_ratio, 1.5);
+        assert_eq!(info.mem_allocator, "jemalloc-5.3.0");
+        assert_eq!(info.mem_aof_buffer, 0);
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let reply = Value::Bulk(vec![
+            data("peak.allocated"), Value::Int(1200000),
+            data("total.allocated"), Value::Int(1000000),
+            data("db.0"), Value::Bulk(vec![
+                data("overhead.hashtable.main"), Value::Int(50000),
+                data("overhead.hashtable.expires"), Value::Int(2000),
+            ]),
+            data("overhead.total"), Value::Int(400000),
+            data("dataset.percentage"), data("85.5"),
+            data("fragmentation"), data("1.5"),
+            data("some.future.field"), Value::Int(7),
+        ]);
+        let stats = MemoryStats::from_redis_value(&reply).unwrap();
+        assert_eq!(stats.total_allocated, 1000000);
+        assert_eq!(stats.fragmentation, 1.5);
+        assert_eq!(stats.keyspaces[&KeyspaceId::new(0)].hashtable_main, 50000);
+    }
+
+    #[test]
+    fn test_overview() {
+        let info = MemoryInfo::from_redis_value(&data(INFO_MEMORY)).unwrap();
+        let overview = MemoryOverview { info: &info, stats: None, estimated_keys: 450000.0 };
+        let other = overview.overhead().into_iter().find(|(label, _)| *label == "other overhead").unwrap();
+        assert_eq!(other.1, 400000 - 300000 - 20000 - 1000);
+        let text = overview.to_string();
+        assert!(text.contains("not explained by keys"));
+        assert!(text.contains("150000"));
+    }
+}
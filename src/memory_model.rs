@@ -0,0 +1,448 @@
+//! Estimation of memory used by a key in a running Redis, as reported by `MEMORY USAGE key SAMPLES 0`.
+//!
+//! The model follows `objectComputeSize` from `object.c` in Redis sources, with sizes of structures
+//! on a 64-bit platform and allocations rounded up to jemalloc size classes. Values are assumed to be
+//! loaded from an RDB file by a server with the default configuration, so compact encodings are
+//! chosen the same way as when Redis loads the file.
+
+const ROBJ: usize = 16;
+const DICT_ENTRY: usize = 24;
+const BUCKET: usize = 8;
+const DICT_MIN_SLOTS: usize = 4;
+const QUICKLIST: usize = 40;
+const ZSET: usize = 16;
+const ZSKIPLIST: usize = 32;
+const ZSKIPLIST_NODE: usize = 24;
+const ZSKIPLIST_LEVEL: usize = 16;
+const ZSKIPLIST_MAXLEVEL: usize = 32;
+const ZSKIPLIST_P: f64 = 0.25;
+const RAX: usize = 24;
+const RAX_NODE: usize = 4;
+const STREAM_CG: usize = 40;
+const STREAM_NACK: usize = 24;
+const STREAM_CONSUMER: usize = 40;
+
+/// Strings up to this length are embedded in the object.
+const EMBSTR_MAX_LENGTH: usize = 44;
+
+// Default limits of compact encodings
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+const MAX_LISTPACK_ENTRIES: usize = 128;
+const MAX_LISTPACK_VALUE: usize = 64;
+
+/// Layout of a value, with everything needed to estimate its memory usage.
+#[derive(Debug, Clone)]
+pub enum Layout {
+    String(Element),
+    /// Listpack, ziplist, intset or zipmap, used by Redis as it is
+    Compact { bytes: usize },
+    QuickList { nodes: Vec<QuickListNode> },
+    /// List stored as individual elements (created by very old versions)
+    List { elements: Vec<Element> },
+    Set { members: Vec<Element> },
+    SortedSet { members: Vec<(Element, Element)> },
+    Hash { fields: Vec<(Element, Element)> },
+    Stream { listpacks: Vec<usize>, groups: u64, pending: u64, consumers: u64 },
+    /// Values of modules are opaque, their serialized size is used
+    Module { serialized_size: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum QuickListNode {
+    /// Listpack (or ziplist) with its size
+    Packed(usize),
+    /// Single big element
+    Plain(usize),
+}
+
+/// String element, integers are stored by Redis in a more compact way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element {
+    pub length: usize,
+    pub integer: Option<i64>,
+}
+
+impl Layout {
+    /// Approximate size of the data of the value, without any overhead.
+    pub fn data_size(&self) -> usize {
+        fn elements_size(elements: &[Element]) -> usize {
+            elements.iter().map(|element| element.length).sum()
+        }
+        fn pairs_size(pairs: &[(Element, Element)]) -> usize {
+            pairs.iter().map(|(first, second)| first.length + second.length).sum()
+        }
+        match self {
+            Layout::String(element) => element.length,
+            Layout::Compact { bytes } => *bytes,
+            Layout::QuickList { nodes } => nodes.iter().map(|node| match node {
+                QuickListNode::Packed(bytes) | QuickListNode::Plain(bytes) => *bytes,
+            }).sum(),
+            Layout::List { elements } => elements_size(elements),
+            Layout::Set { members } => elements_size(members),
+            Layout::SortedSet { members } => members.iter().map(|(member, _)| member.length + 8).sum(),
+            Layout::Hash { fields } => pairs_size(fields),
+            Layout::Stream { listpacks, .. } => listpacks.iter().sum(),
+            Layout::Module { serialized_size } => *serialized_size,
+        }
+    }
+}
+
+impl Element {
+    pub fn new(bytes: &[u8]) -> Self {
+        Element {
+            length: bytes.len(),
+            integer: parse_integer(bytes),
+        }
+    }
+
+    /// Score of a sorted set as stored in a listpack.
+    pub fn score(score: f64) -> Self {
+        if score.fract() == 0.0 && score.abs() < (1u64 << 53) as f64 {
+            Element { length: 0, integer: Some(score as i64) }
+        } else {
+            Element { length: score.to_string().len(), integer: None }
+        }
+    }
+}
+
+/// Parses integers the way `string2ll` does, only canonical representations are integers.
+fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > 20 {
+        return None;
+    }
+    let s = std::str::from_utf8(bytes).ok()?;
+    let value = s.parse::<i64>().ok()?;
+    if value.to_string() == s { Some(value) } else { None }
+}
+
+/// Redis version dependent sizes of structures.
+#[derive(Debug, Clone, Copy)]
+pub struct Model {
+    dict: usize,
+    quicklist_node: usize,
+    stream: usize,
+    /// Small sets of strings use listpacks (since Redis 7.2)
+    set_listpack: bool,
+    /// Lists with a single small node use a plain listpack (since Redis 7.2)
+    list_listpack: bool,
+}
+
+impl Model {
+    /// Model for a server which writes RDB files of given version.
+    pub fn for_rdb_version(version: u32) -> Self {
+        match version {
+            // Redis 6.x
+            0..=9 => Model { dict: 96, quicklist_node: 32, stream: 40, set_listpack: false, list_listpack: false },
+            // Redis 7.0
+            10 => Model { dict: 56, quicklist_node: 40, stream: 80, set_listpack: false, list_listpack: false },
+            // Redis 7.2 and newer
+            _ => Model { dict: 56, quicklist_node: 40, stream: 80, set_listpack: true, list_listpack: true },
+        }
+    }
+
+    /// Memory used by the key, its value and its entry in the keyspace.
+    pub fn memory_usage(&self, key_length: usize, layout: &Layout) -> u64 {
+        (sds_size(key_length) + DICT_ENTRY + self.object_size(layout)) as u64
+    }
+
+    fn dict_size(&self, entries: usize) -> usize {
+        self.dict + BUCKET * slots(entries)
+    }
+
+    fn object_size(&self, layout: &Layout) -> usize {
+        match layout {
+            Layout::String(element) => string_size(element),
+            Layout::Compact { bytes } => ROBJ + jemalloc_size(*bytes),
+            Layout::QuickList { nodes } => {
+                if let [QuickListNode::Packed(bytes)] = nodes[..] {
+                    if self.list_listpack && bytes <= 4096 {
+                        return ROBJ + jemalloc_size(bytes);
+                    }
+                }
+                ROBJ + QUICKLIST + nodes.iter().map(|node| {
+                    self.quicklist_node + jemalloc_size(match node {
+                        QuickListNode::Packed(bytes) | QuickListNode::Plain(bytes) => *bytes,
+                    })
+                }).sum::<usize>()
+            },
+            Layout::List { elements } => {
+                // Elements are packed to listpacks of up to 8 kB
+                let mut nodes = Vec::new();
+                let mut node = Vec::new();
+                for element in elements {
+                    node.push(*element);
+                    if listpack_size(node.iter()) > 8192 {
+                        let last = node.pop().unwrap();
+                        nodes.push(QuickListNode::Packed(listpack_size(node.iter())));
+                        node = vec![last];
+                    }
+                }
+                nodes.push(QuickListNode::Packed(listpack_size(node.iter())));
+                self.object_size(&Layout::QuickList { nodes })
+            },
+            Layout::Set { members } => {
+                if members.len() <= SET_MAX_INTSET_ENTRIES && members.iter().all(|m| m.integer.is_some()) {
+                    let width = members.iter()
+                        .map(|m| match m.integer.unwrap() {
+                            i if i16::try_from(i).is_ok() => 2,
+                            i if i32::try_from(i).is_ok() => 4,
+                            _ => 8,
+                        })
+                        .max()
+                        .unwrap_or(2);
+                    ROBJ + jemalloc_size(8 + members.len() * width)
+                } else if self.set_listpack && is_small(members.iter()) {
+                    ROBJ + jemalloc_size(listpack_size(members.iter()))
+                } else {
+                    ROBJ + self.dict_size(members.len())
+                        + members.iter().map(|m| DICT_ENTRY + sds_size(m.length)).sum::<usize>()
+                }
+            },
+            Layout::SortedSet { members } => {
+                if is_small(members.iter().map(|(member, _)| member)) {
+                    ROBJ + jemalloc_size(listpack_size(members.iter().flat_map(|(member, score)| [member, score])))
+                } else {
+                    let header = jemalloc_size(ZSKIPLIST_NODE + ZSKIPLIST_MAXLEVEL * ZSKIPLIST_LEVEL);
+                    ROBJ + ZSET + ZSKIPLIST + self.dict_size(members.len()) + header
+                        + members.iter().map(|(member, _)| {
+                            sds_size(member.length) + DICT_ENTRY + expected_skiplist_node_size()
+                        }).sum::<usize>()
+                }
+            },
+            Layout::Hash { fields } => {
+                if is_small(fields.iter().flat_map(|(field, value)| [field, value])) {
+                    ROBJ + jemalloc_size(listpack_size(fields.iter().flat_map(|(field, value)| [field, value])))
+                } else {
+                    ROBJ + self.dict_size(fields.len())
+                        + fields.iter().map(|(field, value)| {
+                            DICT_ENTRY + sds_size(field.length) + sds_size(value.length)
+                        }).sum::<usize>()
+                }
+            },
+            Layout::Stream { listpacks, groups, pending, consumers } => {
+                // Approximation, rax trees are counted with two nodes per listpack
+                ROBJ + self.stream + RAX
+                    + listpacks.iter().map(|bytes| bytes + 2 * RAX_NODE).sum::<usize>()
+                    + *groups as usize * (RAX + STREAM_CG)
+                    + *pending as usize * (STREAM_NACK + 2 * RAX_NODE)
+                    + *consumers as usize * (STREAM_CONSUMER + RAX + sds_size(16))
+            },
+            Layout::Module { serialized_size } => ROBJ + serialized_size,
+        }
+    }
+}
+
+/// Whether the elements fit to a listpack with the default configuration.
+fn is_small<'a, I: Iterator<Item = &'a Element> + Clone>(elements: I) -> bool {
+    elements.clone().count() <= MAX_LISTPACK_ENTRIES && elements.into_iter().all(|e| e.length <= MAX_LISTPACK_VALUE)
+}
+
+/// Size of allocation made by jemalloc for a request of `size` bytes.
+pub fn jemalloc_size(size: usize) -> usize {
+    if size <= 8 {
+        return 8;
+    }
+    if size <= 128 {
+        return (size + 15) & !15;
+    }
+    // Four size classes for every power of two
+    let group = usize::BITS - 1 - (size - 1).leading_zeros();
+    let step = 1 << (group - 2);
+    (size + step - 1) & !(step - 1)
+}
+
+/// Allocated size of an sds string of given length.
+pub fn sds_size(length: usize) -> usize {
+    let header = match length {
+        0 => 3,
+        1..=31 => 1,
+        32..=255 => 3,
+        256..=65535 => 5,
+        65536..=0xFFFF_FFFF => 9,
+        _ => 17,
+    };
+    jemalloc_size(header + length + 1)
+}
+
+fn string_size(element: &Element) -> usize {
+    if element.integer.is_some() {
+        ROBJ
+    } else if element.length <= EMBSTR_MAX_LENGTH {
+        // Object and sds with 8-bit header in a single allocation
+        jemalloc_size(ROBJ + 3 + element.length + 1)
+    } else {
+        ROBJ + sds_size(element.length)
+    }
+}
+
+/// Number of slots of a dict created for given number of entries.
+fn slots(entries: usize) -> usize {
+    entries.next_power_of_two().max(DICT_MIN_SLOTS)
+}
+
+/// Expected allocated size of a skiplist node, levels are random.
+fn expected_skiplist_node_size() -> usize {
+    let mut expected = 0.0;
+    let mut probability = 1.0 - ZSKIPLIST_P;
+    for level in 1..=ZSKIPLIST_MAXLEVEL {
+        expected += probability * jemalloc_size(ZSKIPLIST_NODE + level * ZSKIPLIST_LEVEL) as f64;
+        probability *= ZSKIPLIST_P;
+    }
+    expected.round() as usize
+}
+
+/// Size of a listpack with given elements.
+fn listpack_size<'a, I: Iterator<Item = &'a Element>>(elements: I) -> usize {
+    let entries = elements.map(|element| {
+        let size = match element.integer {
+            Some(0..=127) => 1,
+            Some(-4096..=4095) => 2,
+            Some(-32768..=32767) => 3,
+            Some(-8388608..=8388607) => 4,
+            Some(-2147483648..=2147483647) => 5,
+            Some(_) => 9,
+            None => match element.length {
+                0..=63 => 1 + element.length,
+                64..=4095 => 2 + element.length,
+                _ => 5 + element.length,
+            },
+        };
+        let backlen = match size {
+            0..=127 => 1,
+            128..=16383 => 2,
+            16384..=2097151 => 3,
+            2097152..=268435455 => 4,
+            _ => 5,
+        };
+        size + backlen
+    }).sum::<usize>();
+    6 + entries + 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::{jemalloc_size, listpack_size, sds_size, Element, Layout, Model};
+
+    fn element(s: &str) -> Element {
+        Element::new(s.as_bytes())
+    }
+
+    #[test]
+    fn test_jemalloc_size() {
+        let sizes = [(1, 8), (8, 8), (9, 16), (23, 32), (100, 112), (129, 160), (200, 224), (257, 320), (536, 640), (4097, 5120), (20000, 20480)];
+        for (size, expected) in sizes {
+            assert_eq!(jemalloc_size(size), expected, "size {}", size);
+        }
+    }
+
+    #[test]
+    fn test_sds_size() {
+        assert_eq!(sds_size(3), 8);
+        assert_eq!(sds_size(31), 48);
+        assert_eq!(sds_size(32), 48);
+        assert_eq!(sds_size(300), 320);
+    }
+
+    #[test]
+    fn test_element() {
+        assert_eq!(element("123").integer, Some(123));
+        assert_eq!(element("-5").integer, Some(-5));
+        assert_eq!(element("0123").integer, None);
+        assert_eq!(element("+1").integer, None);
+        assert_eq!(element("abc").integer, None);
+        assert_eq!(Element::score(2.0).integer, Some(2));
+        assert_eq!(Element::score(1.5), Element { length: 3, integer: None });
+    }
+
+    #[test]
+    fn test_listpack_size() {
+        // Same listpack as created by HSET h a 1
+        assert_eq!(listpack_size([element("a"), element("1")].iter()), 12);
+    }
+
+    /// Keys of the cases of `MEMORY USAGE` and their layouts, `(name, key length, layout)`.
+    fn cases() -> Vec<(&'static str, usize, Layout)> {
+        let fields = (0..200).map(|i| (element(&format!("field:{:04}", i)), element("v"))).collect();
+        let members = (0..600).map(|i| element(&format!("m{}", i))).collect();
+        vec![
+            // SET foo bar
+            ("string_embstr", 3, Layout::String(element("bar"))),
+            // SET counter 1000
+            ("string_int", 7, Layout::String(element("1000"))),
+            // SET foo <100 bytes>
+            ("string_raw", 3, Layout::String(Element { length: 100, integer: None })),
+            // HSET h a 1
+            ("hash_listpack", 1, Layout::Hash { fields: vec![(element("a"), element("1"))] }),
+            // HSET h field:0000 v ... field:0199 v
+            ("hash_hashtable", 1, Layout::Hash { fields }),
+            // SADD s 1 2 3
+            ("set_intset", 1, Layout::Set { members: vec![element("1"), element("2"), element("3")] }),
+            // SADD s a b c
+            ("set_strings", 1, Layout::Set { members: vec![element("a"), element("b"), element("c")] }),
+            // SADD s m0 ... m599
+            ("set_hashtable", 1, Layout::Set { members }),
+            // RPUSH l a b c
+            ("list_small", 1, Layout::List { elements: vec![element("a"), element("b"), element("c")] }),
+        ]
+    }
+
+    /// Expected `MEMORY USAGE` of the cases with the default configuration, by RDB version of the server.
+    const EXPECTED: [(&str, u32, [u64; 9]); 4] = [
+        ("redis_6_2", 9, [64, 56, 160, 64, 11792, 64, 272, 27536, 136]),
+        ("redis_7_0", 10, [64, 56, 160, 64, 11752, 64, 232, 27496, 144]),
+        // Small sets of strings and small lists are single listpacks since 7.2
+        ("redis_7_2", 11, [64, 56, 160, 64, 11752, 64, 64, 27496, 64]),
+        ("redis_7_4", 12, [64, 56, 160, 64, 11752, 64, 64, 27496, 64]),
+    ];
+
+    #[test]
+    fn test_memory_usage() {
+        for (server, rdb_version, expected) in EXPECTED {
+            let model = Model::for_rdb_version(rdb_version);
+            for ((name, key_length, layout), expected) in cases().iter().zip(expected) {
+                assert_eq!(model.memory_usage(*key_length, layout), expected, "{}_{}", server, name);
+            }
+        }
+    }
+
+    /// Optional cross-check of the model with `MEMORY USAGE` of real servers, listed in `REDIS_ANALYZER_TEST_SERVERS`
+    /// as `version=url` pairs separated by commas (e.g. `6.2=redis://127.0.0.1:6379,7.4=redis://127.0.0.1:6380`).
+    /// The key `redis-analyzer:test` is overwritten on the servers.
+    #[test]
+    #[ignore]
+    fn test_memory_usage_of_servers() {
+        const KEY: &str = "redis-analyzer:test";
+        let servers = std::env::var("REDIS_ANALYZER_TEST_SERVERS").expect("REDIS_ANALYZER_TEST_SERVERS is not set");
+        let long = "x".repeat(100);
+        let commands: [&[&str]; 4] = [&["SET", KEY, "bar"], &["SET", KEY, "1000"], &["SET", KEY, &long], &["HSET", KEY, "a", "1"]];
+        let layouts = [
+            Layout::String(element("bar")),
+            Layout::String(element("1000")),
+            Layout::String(element(&long)),
+            Layout::Hash { fields: vec![(element("a"), element("1"))] },
+        ];
+        let mut mismatches = Vec::new();
+        for server in servers.split(',') {
+            let (version, url) = server.split_once('=').expect("server must be version=url");
+            let model = Model::for_rdb_version(match version {
+                "6.2" => 9,
+                "7.0" => 10,
+                "7.2" => 11,
+                "7.4" => 12,
+                _ => panic!("unknown server version {}", version),
+            });
+            let mut connection = redis::Client::open(url).and_then(|client| client.get_connection()).expect("cannot connect");
+            for (command, layout) in commands.iter().zip(&layouts) {
+                redis::cmd("DEL").arg(KEY).query::<()>(&mut connection).unwrap();
+                redis::cmd(command[0]).arg(&command[1..]).query::<()>(&mut connection).unwrap();
+                let actual: u64 = redis::cmd("MEMORY").arg("USAGE").arg(KEY).arg("SAMPLES").arg(0).query(&mut connection).unwrap();
+                let expected = model.memory_usage(KEY.len(), layout);
+                if actual != expected {
+                    mismatches.push(format!("{} {}: model {}, server {}", version, command.join(" "), expected, actual));
+                }
+            }
+            redis::cmd("DEL").arg(KEY).query::<()>(&mut connection).unwrap();
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt::Debug;
+use std::ops::Add;
+use redis::RedisResult;
+use crate::redis::RedisConnection;
+use crate::results::Datum;
+use crate::snapshot::KeySample;
+
+/// Names of types as returned by `TYPE`, other types are reported as `other`.
+const TYPES: [&str; 6] = ["string", "list", "set", "zset", "hash", "stream"];
+
+/// Totals of a metric of keys under a node of the prefix tree, merged by adding them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MetricTotals {
+    /// Number of keys with known value of the metric
+    pub known: u64,
+    pub sum: f64,
+    pub max: f64,
+    /// Bit set of seen values (e.g. types)
+    pub flags: u64,
+}
+
+impl MetricTotals {
+    /// Totals of a single key with given value.
+    pub fn of(value: f64) -> MetricTotals {
+        MetricTotals { known: 1, sum: value, max: value, flags: 0 }
+    }
+
+    /// Average of the known values.
+    pub fn avg(&self) -> f64 {
+        self.sum / self.known as f64
+    }
+}
+
+impl Add for MetricTotals {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        MetricTotals {
+            known: self.known + other.known,
+            sum: self.sum + other.sum,
+            max: self.max.max(other.max),
+            flags: self.flags | other.flags,
+        }
+    }
+}
+
+/// Node of the prefix tree the columns of a metric are computed for.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricNode<'a> {
+    pub totals: &'a MetricTotals,
+    /// Totals of the parent node, shares are computed from them
+    pub parent: &'a MetricTotals,
+    /// Estimated number of keys of the keyspace represented by one sampled key
+    pub scale: f64,
+}
+
+/// Per-key metric: the commands run for every sampled key, how its values are aggregated
+/// in the prefix tree and which columns of the results it produces.
+///
+/// Values are stored in [`KeySample`], so they are saved in snapshots. A collector also works
+/// for snapshots from other sources (e.g. RDB files) which set the same fields.
+pub trait Collector: Debug + Sync {
+    /// Name used by `--metrics`.
+    fn name(&self) -> &'static str;
+
+    /// Runs commands for a sampled key and stores the value in the sample.
+    /// Returns `false` if the key does not exist anymore.
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool>;
+
+    /// Totals of a single key, `None` if the value is not known for the key.
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals>;
+
+    /// Names of the produced columns, in the order they are rendered.
+    fn columns(&self) -> &'static [&'static str];
+
+    /// Values of the columns for a node with at least one key with known value.
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)>;
+}
+
+/// Memory usage of keys (`MEMORY USAGE`), the base of the memory overview, diffs and forecasts.
+#[derive(Debug)]
+pub struct Memory;
+
+impl Collector for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool> {
+        Ok(match connection.memory_usage(&sample.key)? {
+            Some(memory_usage) => {
+                sample.memory_usage = memory_usage;
+                true
+            },
+            None => false,
+        })
+    }
+
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals> {
+        Some(MetricTotals::of(key.memory_usage as f64))
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        &["memory_usage", "memory_usage_percent", "avg_memory_usage", "estimated_total_memory_usage"]
+    }
+
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)> {
+        vec![
+            ("memory_usage", Datum::Count(node.totals.sum as i64)),
+            ("memory_usage_percent", Datum::Percent(node.totals.sum / node.parent.sum)),
+            ("avg_memory_usage", Datum::Stat(node.totals.avg())),
+            ("estimated_total_memory_usage", Datum::Stat(node.totals.sum * node.scale)),
+        ]
+    }
+}
+
+/// Type of keys (`TYPE`), `mixed` for prefixes with keys of different types.
+#[derive(Debug)]
+pub struct ValueType;
+
+impl ValueType {
+    /// Name of the type of keys with types in `flags`, bits are indexes to `TYPES`, the next bit is for other types.
+    pub fn type_name(flags: u64) -> &'static str {
+        match flags.count_ones() {
+            0 => "",
+            1 => TYPES.get(flags.trailing_zeros() as usize).unwrap_or(&"other"),
+            _ => "mixed",
+        }
+    }
+}
+
+impl Collector for ValueType {
+    fn name(&self) -> &'static str {
+        "type"
+    }
+
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool> {
+        let value_type = connection.value_type(&sample.key)?;
+        if value_type == "none" {
+            return Ok(false);
+        }
+        sample.value_type = Some(value_type);
+        Ok(true)
+    }
+
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals> {
+        let value_type = key.value_type.as_ref()?;
+        let index = TYPES.iter().position(|t| t == value_type).unwrap_or(TYPES.len());
+        Some(MetricTotals { flags: 1 << index, ..MetricTotals::of(0.0) })
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        &["type"]
+    }
+
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)> {
+        vec![("type", Datum::Text(ValueType::type_name(node.totals.flags).to_string()))]
+    }
+}
+
+/// Share of keys with expiration (`PTTL`).
+#[derive(Debug)]
+pub struct Ttl;
+
+impl Collector for Ttl {
+    fn name(&self) -> &'static str {
+        "ttl"
+    }
+
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool> {
+        match connection.pttl(&sample.key)? {
+            -2 => Ok(false),
+            ttl => {
+                sample.ttl = Some(ttl);
+                Ok(true)
+            },
+        }
+    }
+
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals> {
+        key.ttl.map(|ttl| MetricTotals::of(if ttl >= 0 { 1.0 } else { 0.0 }))
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        &["ttl_percent"]
+    }
+
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)> {
+        vec![("ttl_percent", Datum::Percent(node.totals.avg()))]
+    }
+}
+
+/// Number of elements of keys (`LLEN`, `SCARD`, `ZCARD`, `HLEN`, `XLEN`), 1 for strings.
+#[derive(Debug)]
+pub struct Cardinality;
+
+impl Collector for Cardinality {
+    fn name(&self) -> &'static str {
+        "cardinality"
+    }
+
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool> {
+        // The type is read again only if the type collector did not run
+        let value_type = match &sample.value_type {
+            Some(value_type) => value_type.clone(),
+            None => connection.value_type(&sample.key)?,
+        };
+        let command = match value_type.as_str() {
+            "none" => return Ok(false),
+            "list" => "LLEN",
+            "set" => "SCARD",
+            "zset" => "ZCARD",
+            "hash" => "HLEN",
+            "stream" => "XLEN",
+            "string" => {
+                sample.elements = Some(1);
+                return Ok(true);
+            },
+            // Elements of module types cannot be counted
+            _ => return Ok(true),
+        };
+        sample.elements = Some(connection.length(command, &sample.key)?);
+        Ok(true)
+    }
+
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals> {
+        key.elements.map(|elements| MetricTotals::of(elements as f64))
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        &["avg_elements"]
+    }
+
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)> {
+        vec![("avg_elements", Datum::Stat(node.totals.avg()))]
+    }
+}
+
+/// LFU access frequency counters (`OBJECT FREQ`), available only with LFU eviction policies.
+#[derive(Debug)]
+pub struct Frequency;
+
+impl Collector for Frequency {
+    fn name(&self) -> &'static str {
+        "frequency"
+    }
+
+    fn collect(&self, connection: &mut RedisConnection, sample: &mut KeySample) -> RedisResult<bool> {
+        sample.frequency = Some(connection.object_freq(&sample.key)?);
+        Ok(true)
+    }
+
+    fn key_totals(&self, key: &KeySample) -> Option<MetricTotals> {
+        key.frequency.map(|frequency| MetricTotals::of(frequency as f64))
+    }
+
+    fn columns(&self) -> &'static [&'static str] {
+        &["frequency", "avg_frequency", "max_frequency"]
+    }
+
+    fn values(&self, node: MetricNode) -> Vec<(&'static str, Datum)> {
+        vec![
+            ("frequency", Datum::Count(node.totals.sum as i64)),
+            ("avg_frequency", Datum::Stat(node.totals.avg())),
+            ("max_frequency", Datum::Count(node.totals.max as i64)),
+        ]
+    }
+}
+
+/// All collectors in the order of their columns.
+pub const COLLECTORS: [&dyn Collector; 5] = [&Memory, &ValueType, &Ttl, &Cardinality, &Frequency];
+
+/// Finds a collector by its name.
+pub fn collector(name: &str) -> Result<&'static dyn Collector, String> {
+    COLLECTORS.iter().copied().find(|collector| collector.name() == name).ok_or_else(|| {
+        let names = COLLECTORS.iter().map(|collector| collector.name()).collect::<Vec<_>>();
+        format!("unknown metric '{}', available metrics: {}", name, names.join(", "))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::results::Datum;
+    use crate::snapshot::KeySample;
+    use super::{collector, Collector, MetricNode, MetricTotals, Ttl, ValueType};
+
+    fn totals<C: Collector>(collector: &C, keys: &[KeySample]) -> MetricTotals {
+        keys.iter().filter_map(|key| collector.key_totals(key)).fold(MetricTotals::default(), |total, key| total + key)
+    }
+
+    #[test]
+    fn test_collector() {
+        assert_eq!(collector("ttl").unwrap().name(), "ttl");
+        assert_eq!(collector("size").unwrap_err(), "unknown metric 'size', available metrics: memory, type, ttl, cardinality, frequency");
+    }
+
+    #[test]
+    fn test_type() {
+        let key = |value_type: Option<&str>| KeySample { value_type: value_type.map(str::to_string), ..KeySample::new(b"k".to_vec(), 0) };
+        let hashes = totals(&ValueType, &[key(Some("hash")), key(Some("hash")), key(None)]);
+        assert_eq!(hashes.known, 2);
+        assert_eq!(ValueType::type_name(hashes.flags), "hash");
+        let mixed = totals(&ValueType, &[key(Some("hash")), key(Some("list"))]);
+        assert_eq!(ValueType::type_name(mixed.flags), "mixed");
+        assert_eq!(ValueType::type_name(totals(&ValueType, &[key(Some("ReJSON-RL"))]).flags), "other");
+    }
+
+    #[test]
+    fn test_ttl() {
+        let key = |ttl: Option<i64>| KeySample { ttl, ..KeySample::new(b"k".to_vec(), 0) };
+        let totals = totals(&Ttl, &[key(Some(1000)), key(Some(-1)), key(Some(0)), key(Some(-1)), key(None)]);
+        let values = Ttl.values(MetricNode { totals: &totals, parent: &totals, scale: 1.0 });
+        assert!(matches!(values[0], ("ttl_percent", Datum::Percent(p)) if p == 0.5));
+    }
+}
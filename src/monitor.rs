@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use redis::RedisResult;
+use crate::analysis::ResultsOptions;
+use crate::keyspace_info::KeyspaceId;
+use crate::prefix_map::{escape_key, PrefixMap};
+use crate::progress::Progress;
+use crate::redis::RedisConnection;
+use crate::results::{Datum, Results};
+use crate::results2::{NodeColumns, SortOrder};
+use crate::run_info::{RunInfo, SampleInfo};
+use crate::tui::SampledKeys;
+
+/// Column used for charts of the results.
+pub const CHART_COLUMN: &str = "commands";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// Positions of keys in arguments of a command, the command name is argument 0.
+#[derive(Debug, Clone, Copy)]
+enum KeySpec {
+    None,
+    /// Single key at given position
+    At(usize),
+    /// All arguments from given position
+    From(usize),
+    /// Every other argument from position 1 (`MSET`)
+    Pairs,
+    /// First two arguments (`RENAME`)
+    FirstTwo,
+    /// All arguments from position 1 but the last one (blocking commands with a timeout)
+    AllButLast,
+    /// Number of keys at given position followed by the keys
+    NumKeys(usize),
+    /// Destination at position 1, number of keys at position 2 followed by the keys
+    StoreNumKeys,
+    /// Keys after `STREAMS`, followed by the same number of IDs (`XREAD`)
+    Streams,
+}
+
+/// Describes how a command accesses keys, unknown commands are considered to have no keys.
+fn command_spec(command: &str) -> (Access, KeySpec) {
+    use Access::*;
+    use KeySpec::*;
+    match command {
+        "GET" | "GETRANGE" | "SUBSTR" | "STRLEN" | "TYPE" | "TTL" | "PTTL" | "EXPIRETIME" | "PEXPIRETIME" | "DUMP"
+        | "GETBIT" | "BITCOUNT" | "BITPOS" | "BITFIELD_RO" | "LRANGE" | "LINDEX" | "LLEN" | "LPOS"
+        | "HGET" | "HMGET" | "HGETALL" | "HKEYS" | "HVALS" | "HLEN" | "HEXISTS" | "HSTRLEN" | "HRANDFIELD" | "HSCAN"
+        | "HTTL" | "HPTTL" | "SMEMBERS" | "SISMEMBER" | "SMISMEMBER" | "SCARD" | "SRANDMEMBER" | "SSCAN"
+        | "ZRANGE" | "ZRANGEBYSCORE" | "ZREVRANGE" | "ZREVRANGEBYSCORE" | "ZRANGEBYLEX" | "ZREVRANGEBYLEX"
+        | "ZSCORE" | "ZMSCORE" | "ZRANK" | "ZREVRANK" | "ZCARD" | "ZCOUNT" | "ZLEXCOUNT" | "ZSCAN" | "ZRANDMEMBER"
+        | "XRANGE" | "XREVRANGE" | "XLEN" | "XPENDING" | "GEOPOS" | "GEODIST" | "GEOHASH" | "GEOSEARCH"
+        | "GEORADIUS_RO" | "GEORADIUSBYMEMBER_RO" | "SORT_RO" => (Read, At(1)),
+        "OBJECT" | "MEMORY" | "XINFO" => (Read, At(2)),
+        "MGET" | "EXISTS" | "TOUCH" | "PFCOUNT" | "SUNION" | "SINTER" | "SDIFF" => (Read, From(1)),
+        "SINTERCARD" | "ZUNION" | "ZINTER" | "ZDIFF" | "ZINTERCARD" => (Read, NumKeys(1)),
+        "XREAD" => (Read, Streams),
+        "SET" | "SETNX" | "SETEX" | "PSETEX" | "GETSET" | "GETDEL" | "GETEX" | "APPEND" | "INCR" | "DECR"
+        | "INCRBY" | "DECRBY" | "INCRBYFLOAT" | "SETRANGE" | "SETBIT" | "BITFIELD" | "EXPIRE" | "PEXPIRE"
+        | "EXPIREAT" | "PEXPIREAT" | "PERSIST" | "RESTORE" | "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX"
+        | "LINSERT" | "LSET" | "LREM" | "LTRIM" | "LPOP" | "RPOP" | "HSET" | "HMSET" | "HSETNX" | "HDEL"
+        | "HINCRBY" | "HINCRBYFLOAT" | "HEXPIRE" | "HPEXPIRE" | "HPERSIST" | "SADD" | "SREM" | "SPOP"
+        | "ZADD" | "ZINCRBY" | "ZREM" | "ZREMRANGEBYSCORE" | "ZREMRANGEBYRANK" | "ZREMRANGEBYLEX"
+        | "ZPOPMIN" | "ZPOPMAX" | "XADD" | "XDEL" | "XTRIM" | "XACK" | "XCLAIM" | "XAUTOCLAIM" | "XSETID"
+        | "PFADD" | "GEOADD" | "GEORADIUS" | "GEORADIUSBYMEMBER" | "SORT" => (Write, At(1)),
+        "XGROUP" => (Write, At(2)),
+        "DEL" | "UNLINK" | "SUNIONSTORE" | "SINTERSTORE" | "SDIFFSTORE" | "PFMERGE" => (Write, From(1)),
+        "BITOP" => (Write, From(2)),
+        "MSET" | "MSETNX" => (Write, Pairs),
+        "RENAME" | "RENAMENX" | "SMOVE" | "LMOVE" | "BLMOVE" | "RPOPLPUSH" | "BRPOPLPUSH" | "COPY"
+        | "GEOSEARCHSTORE" | "ZRANGESTORE" => (Write, FirstTwo),
+        "BLPOP" | "BRPOP" | "BZPOPMIN" | "BZPOPMAX" => (Write, AllButLast),
+        "LMPOP" | "ZMPOP" => (Write, NumKeys(1)),
+        "BLMPOP" | "BZMPOP" => (Write, NumKeys(2)),
+        "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => (Write, StoreNumKeys),
+        "XREADGROUP" => (Write, Streams),
+        _ => (Read, None),
+    }
+}
+
+fn number(argument: Option<&Vec<u8>>) -> usize {
+    argument.and_then(|a| std::str::from_utf8(a).ok()).and_then(|a| a.parse().ok()).unwrap_or(0)
+}
+
+/// Returns keys accessed by a command.
+fn command_keys(arguments: &[Vec<u8>], spec: KeySpec) -> Vec<&Vec<u8>> {
+    let from = |start: usize, count: usize| arguments.iter().skip(start).take(count).collect::<Vec<_>>();
+    match spec {
+        KeySpec::None => vec![],
+        KeySpec::At(index) => from(index, 1),
+        KeySpec::From(index) => from(index, arguments.len()),
+        KeySpec::Pairs => arguments.iter().skip(1).step_by(2).collect(),
+        KeySpec::FirstTwo => from(1, 2),
+        KeySpec::AllButLast => from(1, arguments.len().saturating_sub(2)),
+        KeySpec::NumKeys(index) => from(index + 1, number(arguments.get(index))),
+        KeySpec::StoreNumKeys => {
+            let mut keys = from(1, 1);
+            keys.extend(from(3, number(arguments.get(2))));
+            keys
+        },
+        KeySpec::Streams => match arguments.iter().position(|a| a.eq_ignore_ascii_case(b"STREAMS")) {
+            Some(index) => from(index + 1, (arguments.len() - index - 1) / 2),
+            None => vec![],
+        },
+    }
+}
+
+/// One command from `MONITOR` output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorLine {
+    /// Unix time in seconds
+    pub timestamp: f64,
+    pub db: KeyspaceId,
+    pub arguments: Vec<Vec<u8>>,
+}
+
+/// Parses a line like `1339518083.107412 [0 127.0.0.1:60866] "set" "foo" "bar"`.
+pub fn parse_line(line: &str) -> Option<MonitorLine> {
+    let (timestamp, rest) = line.trim().split_once(' ')?;
+    let timestamp = timestamp.parse::<f64>().ok()?;
+    let (client, rest) = rest.strip_prefix('[')?.split_once(']')?;
+    let db = client.split(' ').next()?.parse::<i64>().ok()?;
+
+    // Arguments are quoted, with escapes of `sdscatrepr`
+    let mut arguments = Vec::new();
+    let mut bytes = rest.trim_start().bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b' ' => continue,
+            b'"' => {},
+            _ => return None,
+        }
+        let mut argument = Vec::new();
+        loop {
+            match bytes.next()? {
+                b'"' => break,
+                b'\\' => argument.push(match bytes.next()? {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b'x' => {
+                        let hex = [bytes.next()?, bytes.next()?];
+                        u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+                    },
+                    other => other,
+                }),
+                other => argument.push(other),
+            }
+        }
+        arguments.push(argument);
+    }
+    if arguments.is_empty() {
+        return None;
+    }
+    Some(MonitorLine { timestamp, db: KeyspaceId::new(db), arguments })
+}
+
+/// Accesses of a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyAccess {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// Accesses of keys aggregated from `MONITOR` output.
+#[derive(Debug, Default)]
+pub struct AccessStats {
+    pub keyspaces: HashMap<KeyspaceId, HashMap<Vec<u8>, KeyAccess>>,
+    pub commands: u64,
+    /// Commands which do not access keys (`PING`, `INFO`, ...)
+    pub keyless_commands: u64,
+    pub first_timestamp: Option<f64>,
+    pub last_timestamp: Option<f64>,
+    /// Length of the capture in seconds if known, the span of timestamps is used otherwise
+    pub duration: Option<f64>,
+}
+
+impl AccessStats {
+    pub fn add(&mut self, line: &MonitorLine) {
+        self.commands += 1;
+        self.first_timestamp.get_or_insert(line.timestamp);
+        self.last_timestamp = Some(line.timestamp);
+        let command = String::from_utf8_lossy(&line.arguments[0]).to_ascii_uppercase();
+        let (access, spec) = command_spec(&command);
+        let keys = command_keys(&line.arguments, spec);
+        if keys.is_empty() {
+            self.keyless_commands += 1;
+            return;
+        }
+        let keyspace = self.keyspaces.entry(line.db).or_default();
+        for key in keys {
+            let stats = keyspace.entry(key.to_vec()).or_default();
+            match access {
+                Access::Read => stats.reads += 1,
+                Access::Write => stats.writes += 1,
+            }
+        }
+    }
+
+    /// Length of the capture in seconds.
+    pub fn seconds(&self) -> f64 {
+        match (self.duration, self.first_timestamp, self.last_timestamp) {
+            (Some(duration), _, _) => duration,
+            (None, Some(first), Some(last)) => last - first,
+            _ => 0.0,
+        }
+    }
+
+    pub fn run_info(&self, source: String, port: u16) -> RunInfo {
+        let mut keyspaces = self.keyspaces.iter().collect::<Vec<_>>();
+        keyspaces.sort_by_key(|(keyspace, _)| keyspace.as_i64());
+        RunInfo {
+            host: source,
+            port,
+            started_at: self.first_timestamp.map_or_else(SystemTime::now, |ts| UNIX_EPOCH + Duration::from_secs_f64(ts)),
+            samples: keyspaces.into_iter().map(|(keyspace, keys)| SampleInfo {
+                keyspace: *keyspace,
+                sample_size: keys.len() as u64,
+                total_keys: keys.len() as u64,
+                skipped: 0,
+            }).collect(),
+        }
+    }
+
+    /// Accessed keys with their numbers of accesses.
+    pub fn sampled_keys(&self) -> SampledKeys {
+        self.keyspaces.iter().map(|(keyspace, keys)| {
+            (
+                keyspace.to_string(),
+                keys.iter().map(|(key, access)| (escape_key(key), access.reads + access.writes)).collect(),
+            )
+        }).collect()
+    }
+}
+
+/// Runs `MONITOR` on the server for given time, progress is one unit per captured command.
+pub fn capture(host: &str, port: u16, duration: Duration, progress: &dyn Progress) -> RedisResult<AccessStats> {
+    let connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    progress.message(&format!("Monitoring {}:{} for {} seconds", host, port, duration.as_secs_f64()));
+    progress.start(None);
+    let mut stats = AccessStats { duration: Some(duration.as_secs_f64()), ..Default::default() };
+    connection.monitor(duration, |line| {
+        if let Some(line) = parse_line(line) {
+            stats.add(&line);
+            progress.inc(1);
+        }
+    })?;
+    progress.finish();
+    Ok(stats)
+}
+
+/// Reads saved output of `MONITOR` (e.g. from `redis-cli monitor > capture.txt`), other lines are skipped.
+pub fn read_capture(path: &Path) -> std::io::Result<AccessStats> {
+    let mut stats = AccessStats::default();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Some(line) = parse_line(&line?) {
+            stats.add(&line);
+        }
+    }
+    Ok(stats)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct AccessNodeInfo {
+    keys: u64,
+    reads: u64,
+    writes: u64,
+}
+
+impl AccessNodeInfo {
+    fn commands(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    fn add(self, other: Self) -> Self {
+        AccessNodeInfo {
+            keys: self.keys + other.keys,
+            reads: self.reads + other.reads,
+            writes: self.writes + other.writes,
+        }
+    }
+}
+
+/// Columns of nodes of the tree of accessed keys, rates are per second of a capture of `seconds`.
+struct AccessColumns {
+    seconds: f64,
+}
+
+impl NodeColumns<AccessNodeInfo> for AccessColumns {
+    fn columns(&self, info: &AccessNodeInfo, total: &AccessNodeInfo) -> HashMap<String, Datum> {
+        let mut map = HashMap::new();
+        map.insert("keys".to_string(), Datum::Count(info.keys as i64));
+        map.insert("reads".to_string(), Datum::Count(info.reads as i64));
+        map.insert("writes".to_string(), Datum::Count(info.writes as i64));
+        map.insert("commands".to_string(), Datum::Count(info.commands() as i64));
+        map.insert("commands_percent".to_string(), Datum::Percent(info.commands() as f64 / total.commands().max(1) as f64));
+        if self.seconds > 0.0 {
+            map.insert("reads_per_second".to_string(), Datum::Stat(info.reads as f64 / self.seconds));
+            map.insert("writes_per_second".to_string(), Datum::Stat(info.writes as f64 / self.seconds));
+            map.insert("commands_per_second".to_string(), Datum::Stat(info.commands() as f64 / self.seconds));
+        }
+        map
+    }
+
+    fn keys(&self, info: &AccessNodeInfo) -> u64 {
+        info.keys
+    }
+}
+
+/// Builds per-prefix reads, writes and rates of commands. A command accessing multiple keys
+/// is counted for every key.
+pub fn build_results(stats: &AccessStats, options: &ResultsOptions) -> Results {
+    let merged = PrefixMap::new(
+        None,
+        stats.keyspaces.iter().map(|(keyspace, keys)| {
+            let mut prefix_map = PrefixMap::default();
+            for (key, access) in keys {
+                prefix_map.insert(key.clone(), AccessNodeInfo { keys: 1, reads: access.reads, writes: access.writes });
+            }
+            (keyspace.to_string().into_bytes(), prefix_map.simplify())
+        }).collect(),
+    ).sum_values(&AccessNodeInfo::add);
+
+    let seconds = stats.seconds();
+    let mut columns = vec![
+        "keys".to_string(),
+        "reads".to_string(),
+        "writes".to_string(),
+        "commands".to_string(),
+        "commands_percent".to_string(),
+    ];
+    if seconds > 0.0 {
+        columns.extend(["reads_per_second", "writes_per_second", "commands_per_second"].map(str::to_string));
+    }
+
+    Results::from_prefix_map(&merged, &AccessColumns { seconds }, columns, SortOrder::descending("commands"), options)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analysis::ResultsOptions;
+    use crate::keyspace_info::KeyspaceId;
+    use crate::results::Datum;
+    use super::{build_results, parse_line, AccessStats, KeyAccess};
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line(r#"1339518083.107412 [1 127.0.0.1:60866] "set" "foo bar" "a\"b\\c\x01\n""#).unwrap();
+        assert_eq!(line.timestamp, 1339518083.107412);
+        assert_eq!(line.db, KeyspaceId::new(1));
+        assert_eq!(line.arguments, vec![b"set".to_vec(), b"foo bar".to_vec(), b"a\"b\\c\x01\n".to_vec()]);
+
+        let lua = parse_line(r#"1339518083.107412 [0 lua] "get" "foo""#).unwrap();
+        assert_eq!(lua.arguments.len(), 2);
+        assert!(parse_line("OK").is_none());
+        assert!(parse_line(r#"1339518083.1 [0 127.0.0.1:1] "unterminated"#).is_none());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = AccessStats::default();
+        let lines = [
+            r#"1000.0 [0 127.0.0.1:1] "PING""#,
+            r#"1000.5 [0 127.0.0.1:1] "get" "user:1""#,
+            r#"1001.0 [0 127.0.0.1:1] "mset" "user:1" "a" "user:2" "b""#,
+            r#"1001.5 [0 127.0.0.1:1] "xread" "COUNT" "1" "STREAMS" "events" "0""#,
+            r#"1002.0 [0 127.0.0.1:1] "zunionstore" "out" "2" "user:1" "user:2""#,
+        ];
+        for line in lines {
+            stats.add(&parse_line(line).unwrap());
+        }
+        assert_eq!(stats.commands, 5);
+        assert_eq!(stats.keyless_commands, 1);
+        assert_eq!(stats.seconds(), 2.0);
+        let keys = &stats.keyspaces[&KeyspaceId::new(0)];
+        assert_eq!(keys[b"user:1".as_slice()], KeyAccess { reads: 1, writes: 2 });
+        assert_eq!(keys[b"events".as_slice()], KeyAccess { reads: 1, writes: 0 });
+        assert_eq!(keys[b"out".as_slice()], KeyAccess { reads: 0, writes: 1 });
+
+        let results = build_results(&stats, &ResultsOptions { min_count: 1, ..Default::default() });
+        let keyspace = &results.items[0];
+        assert!(matches!(keyspace.columns["commands"], Datum::Count(7)));
+        let user = &keyspace.children[0];
+        assert_eq!(user.name, "user:");
+        assert!(matches!(user.columns["writes_per_second"], Datum::Stat(w) if w == 2.0));
+    }
+}
@@ -1,10 +1,43 @@
 use std::collections::hash_map::Iter;
 use std::collections::HashMap;
 
-const KEY_SEPARATORS: [char; 5] = [':', '|', ',', '.', '_'];
+const KEY_SEPARATORS: [u8; 5] = [b':', b'|', b',', b'.', b'_'];
 
-type Children<T> = HashMap<String, PrefixMap<T>>;
+/// Keys are byte strings, they do not have to be valid UTF-8.
+pub type Children<T> = HashMap<Vec<u8>, PrefixMap<T>>;
 
+/// Whether a name of a node ends with a separator, i.e. it is a prefix shared by keys and not a whole key.
+pub fn is_prefix(name: &[u8]) -> bool {
+    name.last().is_some_and(|b| KEY_SEPARATORS.contains(b))
+}
+
+/// Makes a key printable: valid UTF-8 is kept, other bytes and control characters are escaped like
+/// in `redis-cli` (e.g. `\xff`, `\n`). Backslashes are escaped too, so different keys never look the same.
+pub fn escape_key(key: &[u8]) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for chunk in key.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => escaped.extend(c.to_string().bytes().map(|b| format!("\\x{:02x}", b))),
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    escaped
+}
+
+/// Tree of keys split at separators, every node is a prefix of the keys below it and has a value.
+///
+/// Keys are inserted with [`PrefixMap::insert`] into a map with `Option` values, which are `None`
+/// for prefixes that are not keys themselves. The tree is then folded bottom-up by [`PrefixMap::transform`]
+/// and its variants, which pass the full prefix of a node, its value and the transformed children.
 #[derive(Debug, Clone, Default)]
 pub struct PrefixMap<T> {
     pub value: T,
@@ -12,19 +45,20 @@ pub struct PrefixMap<T> {
 }
 
 impl <V> PrefixMap<Option<V>> {
-    pub fn insert(&mut self, key: String, value: V) {
-        let matches = key.match_indices(KEY_SEPARATORS);
+    /// Inserts a key with nodes for all its prefixes ending with a separator.
+    pub fn insert(&mut self, key: Vec<u8>, value: V) {
+        let matches = key.iter().enumerate().filter(|(_, b)| KEY_SEPARATORS.contains(b));
         let mut node = self;
-        let mut last_prefix = "";
+        let mut last_prefix: &[u8] = b"";
         for (index, _) in matches {
             let prefix = &key[0..(index+1)];
-            node = node.children.entry(prefix.to_string()).or_insert(PrefixMap { value: None, children: HashMap::new() });
+            node = node.children.entry(prefix.to_vec()).or_insert(PrefixMap { value: None, children: HashMap::new() });
             last_prefix = prefix;
         }
-        if last_prefix == key {
+        if last_prefix == key.as_slice() {
             node.value = Some(value);
         } else {
-            node.children.insert(key.to_string(), PrefixMap { value: Some(value), children: HashMap::new() });
+            node.children.insert(key, PrefixMap { value: Some(value), children: HashMap::new() });
         }
     }
 }
@@ -35,55 +69,72 @@ impl <V: Clone + std::fmt::Debug> PrefixMap<Option<V>> {
         self.replace_nodes::<PrefixMap<Option<V>>, _>(&|prefix, value, children| {
             if value.is_none() && children.len() == 1 {
                 let (child_prefix, child) = children.iter().next().unwrap();
-                (child_prefix.to_string(), PrefixMap::new(child.value.clone(), child.children.clone()))
+                (child_prefix.clone(), PrefixMap::new(child.value.clone(), child.children.clone()))
             } else {
-                (prefix.to_string(), PrefixMap::new(value.clone(), children.clone()))
+                (prefix.to_vec(), PrefixMap::new(value.clone(), children.clone()))
             }
         })
     }
 }
 
+impl <V: Clone + Default> PrefixMap<Option<V>> {
+    /// Replaces values by totals of the nodes and all nodes below them, nodes without a value count as the default.
+    pub fn sum_values<F: Fn(V, V) -> V>(&self, add: &F) -> PrefixMap<V> {
+        self.transform_to_prefix_map::<V, _>(&|_, value, children| {
+            let total = children.values().fold(value.clone().unwrap_or_default(), |total, child| add(total, child.value.clone()));
+            (total, children)
+        })
+    }
+}
+
 impl <T> PrefixMap<T> {
+    /// Creates a node, children are keyed by their full prefixes.
     pub fn new(value: T, children: Children<T>) -> Self {
         PrefixMap { value, children }
     }
 
+    /// Transforms the tree bottom-up into a tree of the same shape, the transformer may also modify the children.
     #[must_use]
-    pub fn transform_to_prefix_map<N, F: Fn(&str, &T, Children<N>) -> (N, Children<N>)>(&self, transformer: &F) -> PrefixMap<N> {
+    pub fn transform_to_prefix_map<N, F: Fn(&[u8], &T, Children<N>) -> (N, Children<N>)>(&self, transformer: &F) -> PrefixMap<N> {
         self.transform(&|prefix, value, children| {
             let (new_value, new_children) = transformer(prefix, value, children);
             PrefixMap::new(new_value, new_children)
         })
     }
 
+    /// Folds the tree bottom-up into a single value. The transformer gets an empty prefix,
+    /// prefixes of the children are the keys of the map.
     #[must_use]
-    pub fn transform<R, F: Fn(&str, &T, HashMap<String, R>) -> R>(&self, transformer: &F) -> R {
+    pub fn transform<R, F: Fn(&[u8], &T, HashMap<Vec<u8>, R>) -> R>(&self, transformer: &F) -> R {
         self.replace_nodes::<R, _>(&|prefix, value, children| {
-            (prefix.to_string(), transformer("", value, children))
+            (prefix.to_vec(), transformer(b"", value, children))
         })
     }
 
+    /// Folds the tree bottom-up, the transformer returns a new prefix of the node with the value,
+    /// which becomes its key in the map of children of the parent.
     #[must_use]
-    pub fn replace_nodes<R, F: Fn(&str, &T, HashMap<String, R>) -> (String, R)>(&self, transformer: &F) -> R {
-        self.replace_nodes_inner::<R, F>("", transformer).1
+    pub fn replace_nodes<R, F: Fn(&[u8], &T, HashMap<Vec<u8>, R>) -> (Vec<u8>, R)>(&self, transformer: &F) -> R {
+        self.replace_nodes_inner::<R, F>(b"", transformer).1
     }
 
     #[must_use]
-    fn replace_nodes_inner<R, F: Fn(&str, &T, HashMap<String, R>) -> (String, R)>(&self, prefix: &str, transformer: &F) -> (String, R) {
-        let children: HashMap<String, R> = self.children.iter().map(|(key, child)| {
+    fn replace_nodes_inner<R, F: Fn(&[u8], &T, HashMap<Vec<u8>, R>) -> (Vec<u8>, R)>(&self, prefix: &[u8], transformer: &F) -> (Vec<u8>, R) {
+        let children: HashMap<Vec<u8>, R> = self.children.iter().map(|(key, child)| {
             child.replace_nodes_inner::<R, _>(key, transformer)
         }).collect();
         transformer(prefix, &self.value, children)
     }
 
-    pub fn iter(&self) -> Iter<'_, String, PrefixMap<T>> {
+    /// Iterates over children of the node with their full prefixes.
+    pub fn iter(&self) -> Iter<'_, Vec<u8>, PrefixMap<T>> {
         self.children.iter()
     }
 }
 
 impl <T> IntoIterator for PrefixMap<T> {
-    type Item = <HashMap<String, PrefixMap<T>> as IntoIterator>::Item;
-    type IntoIter = <HashMap<String, PrefixMap<T>> as IntoIterator>::IntoIter;
+    type Item = <HashMap<Vec<u8>, PrefixMap<T>> as IntoIterator>::Item;
+    type IntoIter = <HashMap<Vec<u8>, PrefixMap<T>> as IntoIterator>::IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
         self.children.into_iter()
@@ -96,23 +147,23 @@ mod test {
     #[test]
     fn test1() {
         let mut map = super::PrefixMap::default();
-        map.insert("foo:bar".to_string(), ());
-        map.insert("foo:bar:".to_string(), ());
-        map.insert("foo:bar:1".to_string(), ());
-        assert!(map.children.get("foo:").unwrap().children.get("foo:bar").unwrap().value.is_some());
-        assert!(map.children.get("foo:").unwrap().children.get("foo:bar:").unwrap().children.get("foo:bar:").is_none());
-        assert!(map.children.get("foo:").unwrap().children.get("foo:bar:").unwrap().value.is_some());
-        assert!(map.children.get("foo:").unwrap().children.get("foo:bar:").unwrap().children.get("foo:bar:1").unwrap().value.is_some());
+        map.insert(b"foo:bar".to_vec(), ());
+        map.insert(b"foo:bar:".to_vec(), ());
+        map.insert(b"foo:bar:1".to_vec(), ());
+        assert!(map.children.get(b"foo:".as_slice()).unwrap().children.get(b"foo:bar".as_slice()).unwrap().value.is_some());
+        assert!(!map.children.get(b"foo:".as_slice()).unwrap().children.get(b"foo:bar:".as_slice()).unwrap().children.contains_key(b"foo:bar:".as_slice()));
+        assert!(map.children.get(b"foo:".as_slice()).unwrap().children.get(b"foo:bar:".as_slice()).unwrap().value.is_some());
+        assert!(map.children.get(b"foo:".as_slice()).unwrap().children.get(b"foo:bar:".as_slice()).unwrap().children.get(b"foo:bar:1".as_slice()).unwrap().value.is_some());
     }
 
 
     #[test]
     fn test2() {
         let mut map = super::PrefixMap::default();
-        map.insert("foo".to_string(), ());
-        map.insert("".to_string(), ());
+        map.insert(b"foo".to_vec(), ());
+        map.insert(b"".to_vec(), ());
         assert!(map.value.is_some());
-        assert!(map.children.get("foo").unwrap().value.is_some());
+        assert!(map.children.get(b"foo".as_slice()).unwrap().value.is_some());
     }
 
 
@@ -120,26 +171,26 @@ mod test {
     fn test_simplify() {
         let simplified = {
             let mut map = super::PrefixMap::default();
-            map.insert("foo:bar".to_string(), ());
-            map.insert("foo:bar:".to_string(), ());
-            map.insert("foo:bar:1".to_string(), ());
-            map.insert("foo:bar:2".to_string(), ());
+            map.insert(b"foo:bar".to_vec(), ());
+            map.insert(b"foo:bar:".to_vec(), ());
+            map.insert(b"foo:bar:1".to_vec(), ());
+            map.insert(b"foo:bar:2".to_vec(), ());
             map
         }.simplify();
 
-        let v1 = simplified.children.get("foo:bar").unwrap();
+        let v1 = simplified.children.get(b"foo:bar".as_slice()).unwrap();
         assert_eq!(v1.children.len(), 0);
         assert!(v1.value.is_some());
 
-        let v2 = simplified.children.get("foo:bar:").unwrap();
+        let v2 = simplified.children.get(b"foo:bar:".as_slice()).unwrap();
         assert_eq!(v2.children.len(), 2);
         assert!(v2.value.is_some());
 
-        let v3 = v2.children.get("foo:bar:1").unwrap();
+        let v3 = v2.children.get(b"foo:bar:1".as_slice()).unwrap();
         assert_eq!(v3.children.len(), 0);
         assert!(v3.value.is_some());
 
-        let v4 = v2.children.get("foo:bar:2").unwrap();
+        let v4 = v2.children.get(b"foo:bar:2".as_slice()).unwrap();
         assert_eq!(v4.children.len(), 0);
         assert!(v4.value.is_some());
     }
@@ -149,15 +200,15 @@ mod test {
     fn test_transform() {
         let map = {
             let mut map = super::PrefixMap::default();
-            map.insert("foo:bar".to_string(), ());
-            map.insert("foo:bar:".to_string(), ());
-            map.insert("foo:bar:1".to_string(), ());
-            map.insert("foo:bar:2".to_string(), ());
+            map.insert(b"foo:bar".to_vec(), ());
+            map.insert(b"foo:bar:".to_vec(), ());
+            map.insert(b"foo:bar:1".to_vec(), ());
+            map.insert(b"foo:bar:2".to_vec(), ());
             map
         };
 
         let count = map.transform::<usize, _>(&|_, value, children| {
-            value.map_or(0, |_| 1) + children.iter().map(|(_, v)| v).sum::<usize>()
+            value.map_or(0, |_| 1) + children.values().sum::<usize>()
         });
 
         assert_eq!(count, 4);
@@ -168,15 +219,15 @@ mod test {
     fn test_transform_sum() {
         let map = {
             let mut map = super::PrefixMap::default();
-            map.insert("foo:bar".to_string(), 1);
-            map.insert("foo:bar:".to_string(), 2);
-            map.insert("foo:bar:1".to_string(), 4);
-            map.insert("foo:bar:2".to_string(), 8);
+            map.insert(b"foo:bar".to_vec(), 1);
+            map.insert(b"foo:bar:".to_vec(), 2);
+            map.insert(b"foo:bar:1".to_vec(), 4);
+            map.insert(b"foo:bar:2".to_vec(), 8);
             map
         };
 
         let count = map.transform::<i64, _>(&|_, value, children| {
-            value.map_or(0, |v| v) + children.iter().map(|(_, v)| v).sum::<i64>()
+            value.map_or(0, |v| v) + children.values().sum::<i64>()
         });
 
         assert_eq!(count, 1 + 2 + 4 + 8);
@@ -187,19 +238,39 @@ mod test {
     fn test_simplify_deep() {
         let simplified = {
             let mut map = super::PrefixMap::default();
-            map.insert("bar:1".to_string(), ());
-            map.insert("bar:deep:very:deep".to_string(), ());
+            map.insert(b"bar:1".to_vec(), ());
+            map.insert(b"bar:deep:very:deep".to_vec(), ());
             map
         }.simplify();
 
         println!("{:?}", simplified);
 
-        let v1 = simplified.children.get("bar:1").unwrap();
+        let v1 = simplified.children.get(b"bar:1".as_slice()).unwrap();
         assert_eq!(v1.children.len(), 0);
         assert!(v1.value.is_some());
 
-        let v2 = simplified.children.get("bar:deep:very:deep").unwrap();
+        let v2 = simplified.children.get(b"bar:deep:very:deep".as_slice()).unwrap();
         assert_eq!(v2.children.len(), 0);
         assert!(v2.value.is_some());
     }
+
+
+    #[test]
+    fn test_binary_keys() {
+        let mut map = super::PrefixMap::default();
+        map.insert(b"bin:\xff\x00".to_vec(), ());
+        map.insert(b"bin:\xfe".to_vec(), ());
+        let bin = map.children.get(b"bin:".as_slice()).unwrap();
+        assert!(bin.children.get(b"bin:\xff\x00".as_slice()).unwrap().value.is_some());
+        assert_eq!(bin.children.len(), 2);
+    }
+
+
+    #[test]
+    fn test_escape_key() {
+        assert_eq!(super::escape_key(b"foo:bar"), "foo:bar");
+        assert_eq!(super::escape_key("klíč:1".as_bytes()), "klíč:1");
+        assert_eq!(super::escape_key(b"bin:\xff\x00"), "bin:\\xff\\x00");
+        assert_eq!(super::escape_key(b"a\\b\n"), "a\\\\b\\n");
+    }
 }
@@ -0,0 +1,23 @@
+/// Receives messages and progress of long-running operations, the library itself does not print anything.
+///
+/// Messages describe what is being done, why something is skipped or why a less detailed source is used.
+/// All methods do nothing by default.
+pub trait Progress: Sync {
+    /// Reports what is happening or a warning.
+    fn message(&self, _message: &str) {}
+
+    /// Starts a step with `total` units of work, `None` if the total is not known in advance.
+    fn start(&self, _total: Option<u64>) {}
+
+    /// Units of work of the current step were done.
+    fn inc(&self, _delta: u64) {}
+
+    /// Ends the current step.
+    fn finish(&self) {}
+}
+
+/// Ignores all messages and progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Progress for Silent {}
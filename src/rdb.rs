@@ -0,0 +1,765 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+use crate::memory_model::{Element, Layout, Model, QuickListNode};
+use crate::snapshot::{KeySample, KeyspaceSample, Snapshot};
+
+// Opcodes, see rdb.h in Redis sources
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_FUNCTION_PRE_GA: u8 = 0xF6;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+// Object types
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const TYPE_HASH_METADATA: u8 = 24;
+const TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+// Special string encodings
+const ENC_INT8: u64 = 0;
+const ENC_INT16: u64 = 1;
+const ENC_INT32: u64 = 2;
+const ENC_LZF: u64 = 3;
+
+// Module value opcodes
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+
+/// Oldest and newest supported RDB versions (Redis 6.0 to 7.4).
+const MIN_VERSION: u32 = 9;
+const MAX_VERSION: u32 = 12;
+
+/// One key read from an RDB file.
+#[derive(Debug, Clone)]
+pub struct RdbEntry {
+    pub db: KeyspaceId,
+    pub key: Vec<u8>,
+    /// Unix time in milliseconds
+    pub expire_at: Option<u64>,
+    pub value_type: &'static str,
+    /// Number of elements (fields of a hash, members of a set, ...), 1 for strings
+    pub elements: u64,
+    pub layout: Layout,
+}
+
+fn invalid_data<T>(message: String) -> std::io::Result<T> {
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message))
+}
+
+/// Length-encoded value, special encodings are used for strings only.
+enum Length {
+    Length(u64),
+    Special(u64),
+}
+
+/// Reads keys from an RDB file one by one. The checksum is not verified.
+pub struct RdbParser<R: Read> {
+    reader: R,
+    position: u64,
+    pub version: u32,
+    /// Auxiliary fields (`redis-ver`, `ctime`, `used-mem`, ...)
+    pub aux: BTreeMap<String, String>,
+    db: KeyspaceId,
+    finished: bool,
+}
+
+impl <R: Read> RdbParser<R> {
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut header = [0; 9];
+        reader.read_exact(&mut header)?;
+        if &header[0..5] != b"REDIS" {
+            return invalid_data("not an RDB file".to_string());
+        }
+        let version = std::str::from_utf8(&header[5..9]).ok()
+            .and_then(|version| version.parse::<u32>().ok());
+        let version = match version {
+            Some(version) if (MIN_VERSION..=MAX_VERSION).contains(&version) => version,
+            Some(version) => return invalid_data(format!("unsupported RDB version {}", version)),
+            None => return invalid_data("invalid RDB version".to_string()),
+        };
+        Ok(RdbParser {
+            reader,
+            position: 9,
+            version,
+            aux: BTreeMap::new(),
+            db: KeyspaceId::new(0),
+            finished: false,
+        })
+    }
+
+    /// Returns the next key, `None` at the end of the file.
+    pub fn next_entry(&mut self) -> std::io::Result<Option<RdbEntry>> {
+        let mut expire_at = None;
+        while !self.finished {
+            let opcode = self.read_u8()?;
+            match opcode {
+                OPCODE_EOF => self.finished = true,
+                OPCODE_SELECTDB => self.db = KeyspaceId::new(self.read_length()? as i64),
+                OPCODE_RESIZEDB => {
+                    self.read_length()?;
+                    self.read_length()?;
+                },
+                OPCODE_SLOT_INFO => {
+                    for _ in 0..3 {
+                        self.read_length()?;
+                    }
+                },
+                OPCODE_AUX => {
+                    let key = String::from_utf8_lossy(&self.read_string()?).into_owned();
+                    let value = String::from_utf8_lossy(&self.read_string()?).into_owned();
+                    self.aux.insert(key, value);
+                },
+                OPCODE_EXPIRETIME_MS => expire_at = Some(self.read_u64_le()?),
+                OPCODE_EXPIRETIME => expire_at = Some(self.read_u32_le()? as u64 * 1000),
+                OPCODE_IDLE => { self.read_length()?; },
+                OPCODE_FREQ => { self.read_u8()?; },
+                OPCODE_FUNCTION2 => { self.read_string()?; },
+                OPCODE_MODULE_AUX => {
+                    self.read_length()?; // module id
+                    self.read_length()?; // when opcode
+                    self.read_length()?; // when
+                    self.skip_module_value()?;
+                },
+                OPCODE_FUNCTION_PRE_GA => return invalid_data("functions from Redis 7.0 release candidates are not supported".to_string()),
+                value_type => {
+                    let key = self.read_string()?;
+                    let (value_type, elements, layout) = self.read_value(value_type)?;
+                    return Ok(Some(RdbEntry {
+                        db: self.db,
+                        key,
+                        expire_at,
+                        value_type,
+                        elements,
+                        layout,
+                    }));
+                },
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads a value of given type, returns name of the type, number of elements and layout of the value.
+    fn read_value(&mut self, value_type: u8) -> std::io::Result<(&'static str, u64, Layout)> {
+        Ok(match value_type {
+            TYPE_STRING => ("string", 1, Layout::String(Element::new(&self.read_string()?))),
+            TYPE_LIST | TYPE_SET => {
+                let length = self.read_length()?;
+                let mut elements = Vec::with_capacity(length.min(1 << 16) as usize);
+                for _ in 0..length {
+                    elements.push(Element::new(&self.read_string()?));
+                }
+                if value_type == TYPE_LIST {
+                    ("list", length, Layout::List { elements })
+                } else {
+                    ("set", length, Layout::Set { members: elements })
+                }
+            },
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let length = self.read_length()?;
+                let mut members = Vec::with_capacity(length.min(1 << 16) as usize);
+                for _ in 0..length {
+                    let member = Element::new(&self.read_string()?);
+                    let score = if value_type == TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap())
+                    } else {
+                        // Score as a string, special lengths are used for NaN and infinities
+                        match self.read_u8()? {
+                            253 => f64::NAN,
+                            254 => f64::INFINITY,
+                            255 => f64::NEG_INFINITY,
+                            length => String::from_utf8_lossy(&self.read_bytes(length as usize)?).parse().unwrap_or(0.0),
+                        }
+                    };
+                    members.push((member, Element::score(score)));
+                }
+                ("zset", length, Layout::SortedSet { members })
+            },
+            TYPE_HASH => {
+                let length = self.read_length()?;
+                let mut fields = Vec::with_capacity(length.min(1 << 16) as usize);
+                for _ in 0..length {
+                    fields.push((Element::new(&self.read_string()?), Element::new(&self.read_string()?)));
+                }
+                ("hash", length, Layout::Hash { fields })
+            },
+            TYPE_HASH_METADATA => {
+                self.read_u64_le()?; // minimal expire time
+                let length = self.read_length()?;
+                let mut fields = Vec::with_capacity(length.min(1 << 16) as usize);
+                for _ in 0..length {
+                    self.read_length()?; // TTL of the field
+                    fields.push((Element::new(&self.read_string()?), Element::new(&self.read_string()?)));
+                }
+                ("hash", length, Layout::Hash { fields })
+            },
+            TYPE_HASH_ZIPMAP => self.read_compact("hash", zipmap_length)?,
+            TYPE_LIST_ZIPLIST => self.read_compact("list", ziplist_length)?,
+            TYPE_SET_INTSET => self.read_compact("set", intset_length)?,
+            TYPE_ZSET_ZIPLIST => self.read_compact("zset", |ziplist| Ok(ziplist_length(ziplist)? / 2))?,
+            TYPE_HASH_ZIPLIST => self.read_compact("hash", |ziplist| Ok(ziplist_length(ziplist)? / 2))?,
+            TYPE_HASH_LISTPACK => self.read_compact("hash", |listpack| Ok(listpack_length(listpack)? / 2))?,
+            TYPE_HASH_LISTPACK_EX => {
+                self.read_u64_le()?; // minimal expire time
+                self.read_compact("hash", |listpack| Ok(listpack_length(listpack)? / 3))?
+            },
+            TYPE_ZSET_LISTPACK => self.read_compact("zset", |listpack| Ok(listpack_length(listpack)? / 2))?,
+            TYPE_SET_LISTPACK => self.read_compact("set", listpack_length)?,
+            TYPE_LIST_QUICKLIST => {
+                let count = self.read_length()?;
+                let mut length = 0;
+                let mut nodes = Vec::new();
+                for _ in 0..count {
+                    let node = self.read_string()?;
+                    length += ziplist_length(&node)?;
+                    nodes.push(QuickListNode::Packed(node.len()));
+                }
+                ("list", length, Layout::QuickList { nodes })
+            },
+            TYPE_LIST_QUICKLIST_2 => {
+                let count = self.read_length()?;
+                let mut length = 0;
+                let mut nodes = Vec::new();
+                for _ in 0..count {
+                    let container = self.read_length()?;
+                    let node = self.read_string()?;
+                    if container == QUICKLIST_NODE_CONTAINER_PLAIN {
+                        length += 1;
+                        nodes.push(QuickListNode::Plain(node.len()));
+                    } else {
+                        length += listpack_length(&node)?;
+                        nodes.push(QuickListNode::Packed(node.len()));
+                    }
+                }
+                ("list", length, Layout::QuickList { nodes })
+            },
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                let (length, layout) = self.read_stream(value_type)?;
+                ("stream", length, layout)
+            },
+            TYPE_MODULE_2 => {
+                let start = self.position;
+                self.read_length()?; // module id
+                self.skip_module_value()?;
+                ("module", 1, Layout::Module { serialized_size: (self.position - start) as usize })
+            },
+            _ => return invalid_data(format!("unsupported value type {} at offset {}", value_type, self.position)),
+        })
+    }
+
+    /// Reads a value stored in a single compact encoding.
+    fn read_compact<F>(&mut self, value_type: &'static str, length: F) -> std::io::Result<(&'static str, u64, Layout)>
+    where
+        F: Fn(&[u8]) -> std::io::Result<u64>,
+    {
+        let value = self.read_string()?;
+        Ok((value_type, length(&value)?, Layout::Compact { bytes: value.len() }))
+    }
+
+    /// Reads a stream, returns number of its entries and its layout.
+    fn read_stream(&mut self, value_type: u8) -> std::io::Result<(u64, Layout)> {
+        let count = self.read_length()?;
+        let mut listpacks = Vec::new();
+        for _ in 0..count {
+            self.read_string()?; // master ID
+            listpacks.push(self.read_string()?.len());
+        }
+        let length = self.read_length()?;
+        self.read_length()?; // last ID
+        self.read_length()?;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            self.read_length()?; // first ID
+            self.read_length()?;
+            self.read_length()?; // max deleted entry ID
+            self.read_length()?;
+            self.read_length()?; // entries added
+        }
+        let groups = self.read_length()?;
+        let mut total_pending = 0;
+        let mut total_consumers = 0;
+        for _ in 0..groups {
+            self.read_string()?; // name
+            self.read_length()?; // last ID
+            self.read_length()?;
+            if value_type >= TYPE_STREAM_LISTPACKS_2 {
+                self.read_length()?; // entries read
+            }
+            let pending = self.read_length()?;
+            for _ in 0..pending {
+                self.read_bytes(16 + 8)?; // ID and delivery time
+                self.read_length()?; // delivery count
+            }
+            total_pending += pending;
+            let consumers = self.read_length()?;
+            total_consumers += consumers;
+            for _ in 0..consumers {
+                self.read_string()?; // name
+                self.read_bytes(8)?; // seen time
+                if value_type >= TYPE_STREAM_LISTPACKS_3 {
+                    self.read_bytes(8)?; // active time
+                }
+                let pending = self.read_length()?;
+                self.read_bytes(pending as usize * 16)?;
+            }
+        }
+        Ok((length, Layout::Stream { listpacks, groups, pending: total_pending, consumers: total_consumers }))
+    }
+
+    /// Skips a value serialized by a module with the self-describing format.
+    fn skip_module_value(&mut self) -> std::io::Result<()> {
+        loop {
+            match self.read_length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => { self.read_length()?; },
+                MODULE_OPCODE_FLOAT => { self.read_bytes(4)?; },
+                MODULE_OPCODE_DOUBLE => { self.read_bytes(8)?; },
+                MODULE_OPCODE_STRING => { self.read_string()?; },
+                opcode => return invalid_data(format!("unsupported module opcode {}", opcode)),
+            }
+        }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> std::io::Result<Vec<u8>> {
+        // Lengths come from the file, the buffer grows only with really read data
+        let mut buffer = Vec::new();
+        self.reader.by_ref().take(length as u64).read_to_end(&mut buffer)?;
+        if buffer.len() < length {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.position += length as u64;
+        Ok(buffer)
+    }
+
+    fn read_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32_le(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64_le(&mut self) -> std::io::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_length_encoding(&mut self) -> std::io::Result<Length> {
+        let first = self.read_u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Length((first & 0x3F) as u64),
+            1 => Length::Length((((first & 0x3F) as u64) << 8) | self.read_u8()? as u64),
+            2 => match first {
+                0x80 => Length::Length(u32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()) as u64),
+                0x81 => Length::Length(u64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap())),
+                _ => return invalid_data(format!("invalid length encoding {:#x}", first)),
+            },
+            _ => Length::Special((first & 0x3F) as u64),
+        })
+    }
+
+    fn read_length(&mut self) -> std::io::Result<u64> {
+        match self.read_length_encoding()? {
+            Length::Length(length) => Ok(length),
+            Length::Special(_) => invalid_data(format!("unexpected string encoding at offset {}", self.position)),
+        }
+    }
+
+    fn read_string(&mut self) -> std::io::Result<Vec<u8>> {
+        Ok(match self.read_length_encoding()? {
+            Length::Length(length) => self.read_bytes(length as usize)?,
+            Length::Special(ENC_INT8) => (self.read_u8()? as i8).to_string().into_bytes(),
+            Length::Special(ENC_INT16) => i16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()).to_string().into_bytes(),
+            Length::Special(ENC_INT32) => i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()).to_string().into_bytes(),
+            Length::Special(ENC_LZF) => {
+                let compressed_length = self.read_length()?;
+                let length = self.read_length()?;
+                let compressed = self.read_bytes(compressed_length as usize)?;
+                lzf_decompress(&compressed, length as usize)?
+            },
+            Length::Special(encoding) => return invalid_data(format!("unknown string encoding {}", encoding)),
+        })
+    }
+}
+
+fn lzf_decompress(input: &[u8], length: usize) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(length);
+    let mut i = 0;
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+        if control < 32 {
+            // Literal run
+            let end = i + control + 1;
+            if end > input.len() {
+                return invalid_data("corrupted LZF data".to_string());
+            }
+            output.extend_from_slice(&input[i..end]);
+            i = end;
+        } else {
+            // Back reference
+            let mut run = control >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidData))? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidData))? as usize;
+            i += 1;
+            let offset = ((control & 0x1F) << 8) + low + 1;
+            if offset > output.len() {
+                return invalid_data("corrupted LZF data".to_string());
+            }
+            let start = output.len() - offset;
+            for index in 0..run + 2 {
+                output.push(output[start + index]);
+            }
+        }
+    }
+    if output.len() != length {
+        return invalid_data("corrupted LZF data".to_string());
+    }
+    Ok(output)
+}
+
+fn truncated<T>() -> std::io::Result<T> {
+    invalid_data("truncated compact encoding".to_string())
+}
+
+/// Number of entries in a ziplist.
+fn ziplist_length(ziplist: &[u8]) -> std::io::Result<u64> {
+    if ziplist.len() < 11 {
+        return truncated();
+    }
+    let length = u16::from_le_bytes([ziplist[8], ziplist[9]]);
+    if length < u16::MAX {
+        return Ok(length as u64);
+    }
+    // Too many entries for the header, they have to be counted
+    let mut position = 10;
+    let mut count = 0;
+    while position < ziplist.len() && ziplist[position] != 0xFF {
+        position += if ziplist[position] == 0xFE { 5 } else { 1 };
+        let encoding = *ziplist.get(position).map_or_else(truncated, Ok)?;
+        position += match encoding >> 6 {
+            0 => 1 + (encoding & 0x3F) as usize,
+            1 => 2 + ((((encoding & 0x3F) as usize) << 8) | *ziplist.get(position + 1).map_or_else(truncated, Ok)? as usize),
+            2 => {
+                let bytes = ziplist.get(position + 1..position + 5).map_or_else(truncated, Ok)?;
+                5 + u32::from_be_bytes(bytes.try_into().unwrap()) as usize
+            },
+            _ => 1 + match encoding {
+                0xC0 => 2,
+                0xD0 => 4,
+                0xE0 => 8,
+                0xF0 => 3,
+                0xFE => 1,
+                _ => 0,
+            },
+        };
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Number of elements in a listpack.
+fn listpack_length(listpack: &[u8]) -> std::io::Result<u64> {
+    if listpack.len() < 7 {
+        return truncated();
+    }
+    let length = u16::from_le_bytes([listpack[4], listpack[5]]);
+    if length < u16::MAX {
+        return Ok(length as u64);
+    }
+    // Too many elements for the header, they have to be counted
+    let mut position = 6;
+    let mut count = 0;
+    while position < listpack.len() && listpack[position] != 0xFF {
+        let encoding = listpack[position];
+        let byte = |offset: usize| listpack.get(position + offset).map_or_else(truncated, |b| Ok(*b as usize));
+        let size = if encoding & 0x80 == 0 {
+            1
+        } else if encoding & 0xC0 == 0x80 {
+            1 + (encoding & 0x3F) as usize
+        } else if encoding & 0xE0 == 0xC0 {
+            2
+        } else if encoding & 0xF0 == 0xE0 {
+            2 + (((encoding & 0x0F) as usize) << 8 | byte(1)?)
+        } else {
+            match encoding {
+                0xF0 => 5 + (byte(1)? | byte(2)? << 8 | byte(3)? << 16 | byte(4)? << 24),
+                0xF1 => 3,
+                0xF2 => 4,
+                0xF3 => 5,
+                0xF4 => 9,
+                _ => return invalid_data(format!("invalid listpack encoding {:#x}", encoding)),
+            }
+        };
+        let backlen = match size {
+            // Limits of lpEncodeBacklen
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        position += size + backlen;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Number of elements in an intset.
+fn intset_length(intset: &[u8]) -> std::io::Result<u64> {
+    match intset.get(4..8) {
+        Some(length) => Ok(u32::from_le_bytes(length.try_into().unwrap()) as u64),
+        None => truncated(),
+    }
+}
+
+/// Number of fields in a zipmap.
+fn zipmap_length(zipmap: &[u8]) -> std::io::Result<u64> {
+    let length = *zipmap.first().map_or_else(truncated, Ok)?;
+    if length < 254 {
+        return Ok(length as u64);
+    }
+    let mut position = 1;
+    let mut count = 0;
+    let read_length = |position: &mut usize| -> std::io::Result<usize> {
+        let first = *zipmap.get(*position).map_or_else(truncated, Ok)?;
+        if first < 254 {
+            *position += 1;
+            Ok(first as usize)
+        } else {
+            let bytes = zipmap.get(*position + 1..*position + 5).map_or_else(truncated, Ok)?;
+            *position += 5;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        }
+    };
+    while position < zipmap.len() && zipmap[position] != 0xFF {
+        let key_length = read_length(&mut position)?;
+        position += key_length;
+        let value_length = read_length(&mut position)?;
+        let free = *zipmap.get(position).map_or_else(truncated, Ok)? as usize;
+        position += 1 + value_length + free;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Reads all keys of an RDB file into a snapshot, every key is included, so the totals are exact.
+/// Memory usage of keys is estimated for a server loading the file.
+pub fn snapshot_from_rdb(path: &Path) -> std::io::Result<Snapshot> {
+    let mut parser = RdbParser::new(BufReader::new(File::open(path)?))?;
+    let model = Model::for_rdb_version(parser.version);
+    let mut samples: HashMap<KeyspaceId, (KeyspaceInfo, Vec<KeySample>)> = HashMap::new();
+    let created_at = |aux: &BTreeMap<String, String>| aux.get("ctime").and_then(|ctime| ctime.parse::<u64>().ok());
+    let mut file_time = None;
+    // Entries are sized as they are read, only their samples are kept
+    while let Some(entry) = parser.next_entry()? {
+        // TTLs are relative to the time when the file was created, `ctime` precedes the keys
+        let now = *file_time.get_or_insert_with(|| created_at(&parser.aux).map_or_else(|| std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64), |ctime| ctime * 1000));
+        let (info, keys) = samples.entry(entry.db).or_insert_with(|| (KeyspaceInfo::new(0, 0, 0), Vec::new()));
+        info.keys += 1;
+        let ttl = match entry.expire_at {
+            Some(expire_at) => {
+                info.expires += 1;
+                expire_at.saturating_sub(now) as i64
+            },
+            None => -1,
+        };
+        keys.push(KeySample {
+            memory_usage: model.memory_usage(entry.key.len(), &entry.layout),
+            key: entry.key,
+            value_type: Some(entry.value_type.to_string()),
+            ttl: Some(ttl),
+            elements: Some(entry.elements),
+            frequency: None,
+        });
+    }
+    for (info, keys) in samples.values_mut() {
+        let ttls = keys.iter().filter_map(|key| key.ttl).filter(|ttl| *ttl >= 0).collect::<Vec<_>>();
+        if !ttls.is_empty() {
+            info.avg_ttl = ttls.iter().sum::<i64>() as u64 / ttls.len() as u64;
+        }
+    }
+
+    let keyspaces = KeyspacesInfo {
+        keyspaces: samples.iter().map(|(keyspace, (info, _))| (*keyspace, info.clone())).collect(),
+    };
+    let mut server_info = parser.aux.clone();
+    server_info.insert("rdb_version".to_string(), parser.version.to_string());
+    let mut snapshot = Snapshot::new(path.display().to_string(), 0, server_info, keyspaces);
+    if let Some(created_at) = created_at(&parser.aux) {
+        snapshot.created_at = created_at;
+    }
+    let mut samples = samples.into_iter().collect::<Vec<_>>();
+    samples.sort_by_key(|(keyspace, _)| keyspace.as_i64());
+    snapshot.samples = samples.into_iter().map(|(keyspace, (info, keys))| KeyspaceSample {
+        keyspace,
+        sample_size: info.keys,
+        info,
+        keys,
+        skipped: Default::default(),
+        matching_keys: None,
+    }).collect();
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_model::{Element, Layout};
+    use super::{lzf_decompress, listpack_length, ziplist_length, RdbParser};
+
+    /// Hand-made RDB file in the format written by Redis 7.2 with a few keys of different types.
+    fn rdb() -> Vec<u8> {
+        let mut rdb = b"REDIS0011".to_vec();
+        // aux redis-ver 7.2.4
+        rdb.extend_from_slice(b"\xfa\x09redis-ver\x057.2.4");
+        // aux ctime 1700000000 (int32 encoded)
+        rdb.extend_from_slice(b"\xfa\x05ctime\xc2\x00\xf1\x53\x65");
+        // select db 0, resize db 5 keys 1 expire
+        rdb.extend_from_slice(b"\xfe\x00\xfb\x05\x01");
+        // string foo:bar:test = "1" (int8 encoded)
+        rdb.extend_from_slice(b"\x00\x0cfoo:bar:test\xc0\x01");
+        // string with expiration at 1700000010000 ms
+        rdb.extend_from_slice(b"\xfc");
+        rdb.extend_from_slice(&1700000010000u64.to_le_bytes());
+        rdb.extend_from_slice(b"\x00\x07session\x03abc");
+        // hash listpack {a: 1}
+        let listpack = b"\x0c\x00\x00\x00\x02\x00\x81a\x02\x01\x01\xff";
+        rdb.extend_from_slice(b"\x10\x04hash");
+        rdb.push(listpack.len() as u8);
+        rdb.extend_from_slice(listpack);
+        // set intset {1, 2, 3}
+        let intset = b"\x02\x00\x00\x00\x03\x00\x00\x00\x01\x00\x02\x00\x03\x00";
+        rdb.extend_from_slice(b"\x0b\x03set");
+        rdb.push(intset.len() as u8);
+        rdb.extend_from_slice(intset);
+        // select db 3, quicklist 2 with one packed node and one plain node
+        rdb.extend_from_slice(b"\xfe\x03");
+        rdb.extend_from_slice(b"\x12\x04list\x02\x02");
+        rdb.push(listpack.len() as u8);
+        rdb.extend_from_slice(listpack);
+        rdb.extend_from_slice(b"\x01\x03big");
+        // eof and checksum
+        rdb.extend_from_slice(b"\xff\x00\x00\x00\x00\x00\x00\x00\x00");
+        rdb
+    }
+
+    #[test]
+    fn test_parse() {
+        let rdb = rdb();
+        let mut parser = RdbParser::new(&rdb[..]).unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = parser.next_entry().unwrap() {
+            entries.push(entry);
+        }
+        assert_eq!(parser.version, 11);
+        assert_eq!(parser.aux["redis-ver"], "7.2.4");
+        assert_eq!(parser.aux["ctime"], "1700000000");
+
+        let summary = entries.iter()
+            .map(|e| (e.db.as_i64(), String::from_utf8(e.key.clone()).unwrap(), e.value_type, e.elements, e.expire_at))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (0, "foo:bar:test".to_string(), "string", 1, None),
+            (0, "session".to_string(), "string", 1, Some(1700000010000)),
+            (0, "hash".to_string(), "hash", 1, None),
+            (0, "set".to_string(), "set", 3, None),
+            (3, "list".to_string(), "list", 3, None),
+        ]);
+        assert!(matches!(entries[0].layout, Layout::String(Element { length: 1, integer: Some(1) })));
+        assert!(matches!(entries[2].layout, Layout::Compact { bytes: 12 }));
+        assert!(matches!(&entries[4].layout, Layout::QuickList { nodes } if nodes.len() == 2));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        assert!(RdbParser::new(&b"REDIS0003\xff"[..]).is_err());
+        assert!(RdbParser::new(&b"NOTREDIS0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_lzf() {
+        // "aaaaaaaaaa": literal "a" and a back reference of length 9 at offset 1
+        assert_eq!(lzf_decompress(b"\x00a\xe0\x00\x00", 10).unwrap(), b"aaaaaaaaaa");
+        assert!(lzf_decompress(b"\x00a\x20\x05", 4).is_err());
+    }
+
+    #[test]
+    fn test_counted_lengths() {
+        // Headers with the maximal count force counting of the entries
+        let listpack = b"\x00\x00\x00\x00\xff\xff\x81a\x02\x01\x01\xe0\x05hello\x07\xff";
+        assert_eq!(listpack_length(listpack).unwrap(), 3);
+        let ziplist = b"\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff\x00\x01a\x03\xfe\x07\x04\xc0\x01\x00\xff";
+        assert_eq!(ziplist_length(ziplist).unwrap(), 3);
+    }
+
+    /// Listpack with the maximal count in the header and a string entry of `size` bytes (encoding and data)
+    /// followed by an integer entry, the entry is skipped by its backlen.
+    fn listpack_with_entry(size: usize, backlen: usize) -> Vec<u8> {
+        let mut listpack = b"\x00\x00\x00\x00\xff\xff".to_vec();
+        if size <= 4097 {
+            let length = size - 2;
+            listpack.extend([0xE0 | (length >> 8) as u8, length as u8]);
+            listpack.resize(listpack.len() + length, b'x');
+        } else {
+            let length = size - 5;
+            listpack.push(0xF0);
+            listpack.extend((length as u32).to_le_bytes());
+            listpack.resize(listpack.len() + length, b'x');
+        }
+        listpack.resize(listpack.len() + backlen, 0);
+        listpack.extend(b"\x01\x01\xff");
+        listpack
+    }
+
+    #[test]
+    fn test_listpack_backlen() {
+        for (size, backlen) in [(127, 1), (128, 2), (16382, 2), (16383, 3), (2097150, 3), (2097151, 4)] {
+            assert_eq!(listpack_length(&listpack_with_entry(size, backlen)).unwrap(), 2, "entry of {} bytes", size);
+        }
+    }
+
+    #[test]
+    fn test_truncated_string() {
+        // Length of 2^32 bytes with only a few bytes following, nothing that big is allocated
+        let mut parser = RdbParser::new(&b"REDIS0011\x81\x00\x00\x00\x01\x00\x00\x00\x00abc"[..]).unwrap();
+        let error = parser.read_string().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
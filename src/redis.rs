@@ -1,14 +1,21 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use redis::{ConnectionAddr, ConnectionInfo, RedisConnectionInfo, RedisResult};
-use crate::keyspace_info::KeyspaceId;
-use crate::KeyspacesInfo;
+use crate::keyspace_info::{KeyspaceId, KeyspacesInfo};
+use crate::memory_info::{MemoryInfo, MemoryStats};
+use crate::progress::Progress;
 
-pub struct RedisConnection {
+/// Connection to a server, commands are retried on a new connection after connection errors.
+/// Retries are reported to the `Progress`.
+pub struct RedisConnection<'p> {
     connection_info: ConnectionInfo,
     connection: redis::Connection,
+    progress: &'p dyn Progress,
 }
 
-impl RedisConnection {
-    pub fn open(host: String, port: u16, db: KeyspaceId) -> RedisResult<Self> {
+impl <'p> RedisConnection<'p> {
+    pub fn open(host: String, port: u16, db: KeyspaceId, progress: &'p dyn Progress) -> RedisResult<Self> {
         let connection_info = ConnectionInfo {
             addr: ConnectionAddr::Tcp(host, port),
             redis: RedisConnectionInfo {
@@ -19,6 +26,7 @@ impl RedisConnection {
         Ok(Self {  
             connection_info: connection_info.clone(),
             connection: redis::Client::open(connection_info)?.get_connection()?,
+            progress,
         })
     }
 
@@ -26,11 +34,12 @@ impl RedisConnection {
         let mut retries = 0;
         loop {
             let result = f(&mut self.connection);
-            if result.is_ok() || retries >= 3 {
-                return result;
-            }
-            let err: redis::RedisError = result.err().unwrap();
-            eprintln!("Error running command - creating new connection and retrying: {err:?}");
+            let err = match result {
+                // Error replies of the server would be the same on a new connection
+                Err(err) if err.code().is_none() && retries < 3 => err,
+                result => return result,
+            };
+            self.progress.message(&format!("Error running command - creating new connection and retrying: {err:?}"));
             std::thread::sleep(std::time::Duration::from_secs(match retries {
                 0 => 1,
                 1 => 2,
@@ -41,17 +50,128 @@ impl RedisConnection {
         }
     }
 
+    /// Switches to another database, it is used also after reconnecting.
+    pub fn select(&mut self, db: KeyspaceId) -> RedisResult<()> {
+        self.connection_info.redis.db = db.as_i64();
+        self.use_connection(|conn| redis::cmd("SELECT").arg(db.as_i64()).query(conn))
+    }
+
     pub fn keyspaces(&mut self) -> RedisResult<KeyspacesInfo> {
         self.use_connection(|conn| redis::cmd("INFO").arg("keyspace").query(conn))
     }
 
-    pub fn scan(&mut self, limit: u64) -> RedisResult<Vec<String>> {
+    pub fn memory_info(&mut self) -> RedisResult<MemoryInfo> {
+        self.use_connection(|conn| redis::cmd("INFO").arg("memory").query(conn))
+    }
+
+    pub fn memory_stats(&mut self) -> RedisResult<MemoryStats> {
+        self.use_connection(|conn| redis::cmd("MEMORY").arg("STATS").query(conn))
+    }
+
+    /// Returns fields of given `INFO` section.
+    pub fn info(&mut self, section: &str) -> RedisResult<BTreeMap<String, String>> {
+        let info: String = self.use_connection(|conn| redis::cmd("INFO").arg(section).query(conn))?;
+        Ok(info.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// Runs one iteration of `SCAN`, returns the next cursor and found keys.
+    pub fn scan(&mut self, cursor: u64, pattern: Option<&str>, count: u64) -> RedisResult<(u64, Vec<Vec<u8>>)> {
         self.use_connection(|conn| {
-            Ok(redis::cmd("SCAN").arg(0).arg("COUNT").arg(limit).clone().iter(conn)?.collect::<Vec<_>>())
+            let mut cmd = redis::cmd("SCAN");
+            cmd.arg(cursor);
+            if let Some(pattern) = pattern {
+                cmd.arg("MATCH").arg(pattern);
+            }
+            cmd.arg("COUNT").arg(count).query(conn)
         })
     }
 
-    pub fn memory_usage(&mut self, key: &str) -> RedisResult<u64> {
+    /// Returns value of a configuration parameter, `None` if the server does not know it.
+    pub fn config_get(&mut self, parameter: &str) -> RedisResult<Option<String>> {
+        let values: Vec<String> = self.use_connection(|conn| redis::cmd("CONFIG").arg("GET").arg(parameter).query(conn))?;
+        Ok(values.into_iter().nth(1))
+    }
+
+    pub fn config_set(&mut self, parameter: &str, value: &str) -> RedisResult<()> {
+        self.use_connection(|conn| redis::cmd("CONFIG").arg("SET").arg(parameter).arg(value).query(conn))
+    }
+
+    /// Returns LFU access frequency counter of a key, works only with LFU eviction policies.
+    pub fn object_freq(&mut self, key: &[u8]) -> RedisResult<u64> {
+        self.use_connection(|conn| redis::cmd("OBJECT").arg("FREQ").arg(key).query(conn))
+    }
+
+    /// Returns type of a key, `none` if the key does not exist.
+    pub fn value_type(&mut self, key: &[u8]) -> RedisResult<String> {
+        self.use_connection(|conn| redis::cmd("TYPE").arg(key).query(conn))
+    }
+
+    /// Runs a command returning a number of elements of a key (e.g. `HLEN`).
+    pub fn length(&mut self, command: &str, key: &[u8]) -> RedisResult<u64> {
+        self.use_connection(|conn| redis::cmd(command).arg(key).query(conn))
+    }
+
+    /// Returns remaining time to live in milliseconds, -1 without expiration, -2 if the key does not exist.
+    pub fn pttl(&mut self, key: &[u8]) -> RedisResult<i64> {
+        self.use_connection(|conn| redis::cmd("PTTL").arg(key).query(conn))
+    }
+
+    /// Returns memory usage of a key, `None` if the key does not exist anymore.
+    pub fn memory_usage(&mut self, key: &[u8]) -> RedisResult<Option<u64>> {
         self.use_connection(|conn| redis::cmd("MEMORY").arg("USAGE").arg(key).arg("SAMPLES").arg(0).query(conn))
     }
+
+    /// Runs `MONITOR` for given time and passes every received line to `on_line`.
+    /// The connection cannot be used for other commands afterwards.
+    pub fn monitor<F: FnMut(&str)>(mut self, duration: Duration, mut on_line: F) -> RedisResult<()> {
+        let deadline = Instant::now() + duration;
+        redis::cmd("MONITOR").query::<()>(&mut self.connection)?;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            self.connection.set_read_timeout(Some(remaining))?;
+            match self.connection.recv_response() {
+                Ok(redis::Value::Status(line)) => on_line(&line),
+                Ok(_) => {},
+                Err(e) if e.is_timeout() => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Subscribes to channels matching `patterns` until `duration` passes or `stop` is set and passes
+    /// the channel and the payload of every message to `on_message`.
+    /// The connection cannot be used for other commands afterwards.
+    pub fn psubscribe<F: FnMut(&[u8], &[u8])>(mut self, patterns: &[&str], duration: Duration, stop: &AtomicBool, mut on_message: F) -> RedisResult<()> {
+        let deadline = Instant::now() + duration;
+        // Confirmations of further patterns are received as other messages and skipped
+        redis::cmd("PSUBSCRIBE").arg(patterns).query::<()>(&mut self.connection)?;
+        while !stop.load(Ordering::Relaxed) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            // Short timeouts let `stop` be checked while no messages arrive
+            self.connection.set_read_timeout(Some(remaining.min(Duration::from_millis(200))))?;
+            match self.connection.recv_response() {
+                Ok(redis::Value::Bulk(values)) => {
+                    if let [redis::Value::Data(kind), _, redis::Value::Data(channel), redis::Value::Data(payload)] = values.as_slice() {
+                        if kind == b"pmessage" {
+                            on_message(channel, payload);
+                        }
+                    }
+                },
+                Ok(_) => {},
+                Err(e) if e.is_timeout() => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 }
@@ -2,33 +2,59 @@ use std::cmp::max;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use csv::Writer;
+use crate::analysis::ResultsOptions;
+use crate::results2::{pruned, sorted, walk, SortOrder, Tree};
 
+/// Tree of items with named columns, the output of every analysis.
 #[derive(Debug, Clone)]
 pub struct Results {
+    /// Top-level items, keyspaces named by their database number
     pub items: Vec<Item>,
+    /// Names of columns in the order they are rendered, items do not have to have all of them
     pub columns: Vec<String>,
 }
 
+/// Prefix or key in the results.
 #[derive(Debug, Clone)]
 pub struct Item {
+    /// Printable name, the whole prefix or key with bytes which are not valid UTF-8 escaped
     pub name: String,
     pub columns: HashMap<String, Datum>,
+    /// Longer prefixes and keys starting with the name
     pub children: Vec<Item>,
 }
 
+/// Value of a column.
 #[derive(Debug, Clone)]
 pub enum Datum {
+    /// Share between 0 and 1, rendered as percent
     Percent(f64),
+    /// Exact number, e.g. of sampled keys
     Count(i64),
+    /// Estimate or average
     Stat(f64),
+    Text(String),
 }
 
-impl ToString for Datum {
-    fn to_string(&self) -> String {
+impl Datum {
+    /// Numeric value of the datum, used for sorting and charts.
+    pub fn value(&self) -> f64 {
         match self {
-            Datum::Percent(p) => format!("{:.1}%", p * 100.0),
-            Datum::Count(c) => c.to_string(),
-            Datum::Stat(s) => format!("{:.1}", s),
+            Datum::Percent(p) => *p,
+            Datum::Count(c) => *c as f64,
+            Datum::Stat(s) => *s,
+            Datum::Text(_) => 0.0,
+        }
+    }
+}
+
+impl Display for Datum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Datum::Percent(p) => write!(f, "{:.1}%", p * 100.0),
+            Datum::Count(c) => write!(f, "{}", c),
+            Datum::Stat(s) => write!(f, "{:.1}", s),
+            Datum::Text(t) => f.write_str(t),
         }
     }
 }
@@ -36,154 +62,87 @@ impl ToString for Datum {
 const CHILD_PREFIX: &str = "  ";
 const COLUMNS_SEPARATOR: &str = " | ";
 
-impl Item {
-    #[must_use]
-    fn name_column_width(&self) -> usize {
-        max(
-            self.name.len(),
-            self.children
-                .iter()
-                .map(|c| c.name_column_width() + CHILD_PREFIX.len())
-                .max()
-                .unwrap_or(0),
-        )
-    }
+/// Rendered names (indented by depth) and values of columns of every node in the order of rows.
+fn rows<T: Tree, I: IntoIterator<Item = T>>(nodes: I, columns: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut rows = Vec::new();
+    walk(nodes, &mut |node: &T, depth| {
+        let name = format!("{}{}", CHILD_PREFIX.repeat(depth), node.name());
+        let data = columns.iter().map(|column| node.column(column).map_or_else(String::new, |d| d.to_string())).collect();
+        rows.push((name, data));
+        true
+    });
+    rows
 }
 
-impl Results {
-    #[must_use]
-    fn names_width(&self) -> usize {
-        self.items.iter().map(|i| i.name_column_width()).max().unwrap_or(0)
-    }
+/// Writes a table of the nodes with a header, values are aligned in columns and names are indented by depth.
+pub fn write_text<T: Tree, I: IntoIterator<Item = T>, W: std::fmt::Write>(nodes: I, columns: &[String], writer: &mut W) -> std::fmt::Result {
+    let rows = rows(nodes, columns);
+    let names_width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+    let columns_widths = columns.iter().enumerate()
+        .map(|(index, column)| rows.iter().map(|(_, data)| data[index].chars().count()).fold(column.chars().count(), max))
+        .collect::<Vec<_>>();
 
-    #[must_use]
-    fn column_width(&self, column: &str) -> usize {
-        fn item_width(item: &Item, column: &str) -> usize {
-            let mut width = item.columns.get(column).map_or(0, |s| s.to_string().len());
-            for child in &item.children {
-                width = max(width, item_width(child, column));
-            }
-            width
-        }
-        self.items.iter()
-            .map(|i| item_width(i, column))
-            .max()
-            .unwrap_or(0)
+    // Header
+    writer.write_str(&" ".repeat(names_width))?;
+    for (column, width) in columns.iter().zip(&columns_widths) {
+        write!(writer, "{}{:>width$}", COLUMNS_SEPARATOR, column, width = width)?;
     }
+    writer.write_char('\n')?;
 
-    pub fn write_to_csv<W: std::io::Write>(&self, csv_writer: &mut Writer<W>) -> csv::Result<()> {
-        // Header
-        csv_writer.write_field("")?;
-        for column in &self.columns {
-            csv_writer.write_field(column.to_string())?;
+    // Nodes
+    for (name, data) in rows {
+        write!(writer, "{:<width$}", name, width = names_width)?;
+        for (datum, width) in data.iter().zip(&columns_widths) {
+            write!(writer, "{}{:>width$}", COLUMNS_SEPARATOR, datum, width = width)?;
         }
-        csv_writer.write_record(None::<&[u8]>)?;
-
-        // Items
-        let results_printer = ResultsPrinter::new(self);
-        for item in self.items.iter() {
-            for row in results_printer.item_strings(item, 0) {
-                for (_, datum, _) in row.iter() {
-                    csv_writer.write_field(datum)?;
-                }
-                csv_writer.write_record(None::<&[u8]>)?;
-            }
-        }
-
-        Ok(())
+        writer.write_char('\n')?;
     }
+    Ok(())
 }
 
-impl Display for Results {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let results_printer = ResultsPrinter::new(self);
-        f.write_str(&results_printer.header())?;
-        for i in self.items.iter() {
-            f.write_str(&results_printer.item(i, 0))?;
-        }
-        Ok(())
+/// Writes a header with the column names and a row for every node, names are indented by depth.
+pub fn write_csv<T: Tree, I: IntoIterator<Item = T>, W: std::io::Write>(nodes: I, columns: &[String], csv_writer: &mut Writer<W>) -> csv::Result<()> {
+    // Header
+    csv_writer.write_field("")?;
+    for column in columns {
+        csv_writer.write_field(column)?;
     }
-}
+    csv_writer.write_record(None::<&[u8]>)?;
 
-struct ResultsPrinter<'a> {
-    results: &'a Results,
-    names_width: usize,
-    columns_widths: Vec<usize>,
-}
-
-enum Alignment {
-    Left,
-    Right,
-}
-
-impl <'a> ResultsPrinter<'a> {
-    fn new(results: &'a Results) -> Self {
-        Self {
-            results,
-            names_width: results.names_width(),
-            columns_widths: results.columns.iter().map(|c| max(c.len(), results.column_width(c))).collect(),
+    // Nodes
+    for (name, data) in rows(nodes, columns) {
+        csv_writer.write_field(name)?;
+        for datum in data {
+            csv_writer.write_field(datum)?;
         }
+        csv_writer.write_record(None::<&[u8]>)?;
     }
+    Ok(())
+}
 
-    fn header(&self) -> String {
-        let mut s = String::new();
-        s.push_str(&" ".repeat(self.names_width));
-        for (index, column) in self.results.columns.iter().enumerate() {
-            s.push_str(COLUMNS_SEPARATOR);
-            s.push_str(&" ".repeat(self.columns_widths[index] - column.len()));
-            s.push_str(&column.to_string());
-        }
-        s.push_str("\n");
-        s
+impl Results {
+    /// Removes items deeper than `max_depth`, top-level items are at depth 1.
+    pub fn truncate_depth(&mut self, max_depth: usize) {
+        let options = ResultsOptions { max_depth: Some(max_depth), min_count: 0, ..Default::default() };
+        let items = pruned(&self.items, &options).iter().map(Item::from_tree).collect();
+        self.items = items;
     }
 
-    fn item_strings(&self, item: &Item, depth: usize) -> Vec<Vec<(Alignment, String, usize)>> {
-        let mut rows = Vec::new();
-
-        let mut row = Vec::new();
-        let mut name = String::new();
-        if depth > 0 {
-            name.push_str(&" ".repeat((depth - 1) * CHILD_PREFIX.len()));
-            name.push_str(CHILD_PREFIX);
-        }
-        name.push_str(&item.name);
-        row.push((Alignment::Left, name, self.names_width));
-        for (index, column) in self.results.columns.iter().enumerate() {
-            let datum = item.columns.get(column).map_or_else(|| "".to_string(), |d| d.to_string());
-            row.push((Alignment::Right, datum, self.columns_widths[index]));
-        }
-        rows.push(row);
+    /// Sorts items on every level by values of a column in descending order.
+    pub fn sort_by_column(&mut self, column: &str) {
+        let items = sorted(&self.items, SortOrder::descending(column)).iter().map(Item::from_tree).collect();
+        self.items = items;
+    }
 
-        for child in &item.children {
-            rows.extend(self.item_strings(child, depth + 1));
-        }
-        rows
+    /// Writes a header with the column names and a row for every item, names are indented by depth.
+    pub fn write_to_csv<W: std::io::Write>(&self, csv_writer: &mut Writer<W>) -> csv::Result<()> {
+        write_csv(&self.items, &self.columns, csv_writer)
     }
+}
 
-    fn item(&self, item: &Item, depth: usize) -> String {
-        let mut s = String::new();
-        for row in self.item_strings(item, depth) {
-            let item_strings = row;
-            let columns_count = item_strings.len();
-            for (index, (alignment, datum, width)) in item_strings.into_iter().enumerate() {
-                let padding = &" ".repeat(width - datum.len());
-                match alignment {
-                    Alignment::Left => {
-                        s.push_str(&datum);
-                        s.push_str(padding);
-                    },
-                    Alignment::Right => {
-                        s.push_str(padding);
-                        s.push_str(&datum);
-                    },
-                }
-                if index < columns_count - 1 {
-                    s.push_str(COLUMNS_SEPARATOR);
-                }
-            }
-            s.push_str("\n");
-        }
-        return s;
+impl Display for Results {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write_text(&self.items, &self.columns, f)
     }
 }
 
@@ -243,6 +202,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_truncate_depth() {
+        fn item(name: &str, children: Vec<Item>) -> Item {
+            Item { name: name.to_string(), columns: HashMap::new(), children }
+        }
+        let mut result = Results {
+            columns: vec![],
+            items: vec![item("a", vec![item("b", vec![item("c", vec![])])])],
+        };
+        result.truncate_depth(2);
+        assert_eq!(result.items[0].children[0].name, "b");
+        assert!(result.items[0].children[0].children.is_empty());
+    }
+
+    #[test]
+    fn test_sort_by_column() {
+        fn item(name: &str, value: i64, children: Vec<Item>) -> Item {
+            Item { name: name.to_string(), columns: HashMap::from([("c".to_string(), Datum::Count(value))]), children }
+        }
+        let mut result = Results {
+            columns: vec!["c".to_string()],
+            items: vec![item("a", 1, vec![]), item("b", 3, vec![item("c", 1, vec![]), item("d", 2, vec![])])],
+        };
+        result.sort_by_column("c");
+        assert_eq!(result.items[0].name, "b");
+        assert_eq!(result.items[0].children[0].name, "d");
+    }
+
     // #[test]
     // fn test_merge_results() {
     //     let results = vec![
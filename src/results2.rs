@@ -1,5 +1,284 @@
-pub trait Tree {
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use crate::analysis::ResultsOptions;
+use crate::prefix_map::{escape_key, PrefixMap};
+use crate::results::{Datum, Item, Results};
+
+/// Tree of named nodes with columns, the common shape of [`Item`]s of results and of a [`PrefixMap`].
+///
+/// Renderers, pruning and sorting are written once over this trait, so they work with any tree.
+/// Nodes are cheap handles (usually references), children are produced on demand.
+pub trait Tree: Sized {
     type Children: IntoIterator<Item = Self>;
 
+    /// Nodes directly below this one, in the order they are rendered.
     fn children(&self) -> Self::Children;
+
+    /// Printable name of the node.
+    fn name(&self) -> String;
+
+    /// All columns of the node.
+    fn columns(&self) -> HashMap<String, Datum>;
+
+    /// Value of a column, `None` if the node does not have it.
+    fn column(&self, column: &str) -> Option<Datum> {
+        self.columns().remove(column)
+    }
+
+    /// Number of keys under the node, `None` if it is not known.
+    fn keys(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl <'a> Tree for &'a Item {
+    type Children = std::slice::Iter<'a, Item>;
+
+    fn children(&self) -> Self::Children {
+        self.children.iter()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn columns(&self) -> HashMap<String, Datum> {
+        self.columns.clone()
+    }
+
+    fn column(&self, column: &str) -> Option<Datum> {
+        self.columns.get(column).cloned()
+    }
+}
+
+/// Columns of nodes of a [`PrefixMap`] with values of type `T`, it may hold settings of the analysis (e.g. its duration).
+pub trait NodeColumns<T> {
+    /// Columns of a node, `parent` is the value of its parent node (for shares).
+    fn columns(&self, value: &T, parent: &T) -> HashMap<String, Datum>;
+
+    /// Number of keys under the node.
+    fn keys(&self, value: &T) -> u64;
+}
+
+/// Node of a [`PrefixMap`] browsed as a [`Tree`], named by its full prefix. Children are ordered by their prefixes.
+#[derive(Debug)]
+pub struct PrefixNode<'a, T, C> {
+    /// Full prefix of the node
+    pub prefix: &'a [u8],
+    pub map: &'a PrefixMap<T>,
+    parent: &'a T,
+    node_columns: &'a C,
+}
+
+impl <'a, T, C: NodeColumns<T>> PrefixNode<'a, T, C> {
+    /// Children of the root of a map as top-level nodes of a tree.
+    pub fn roots(map: &'a PrefixMap<T>, node_columns: &'a C) -> Vec<Self> {
+        let mut nodes = map.iter()
+            .map(|(prefix, child)| PrefixNode { prefix: prefix.as_slice(), map: child, parent: &map.value, node_columns })
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|node| node.prefix);
+        nodes
+    }
+}
+
+impl <'a, T, C: NodeColumns<T>> Tree for PrefixNode<'a, T, C> {
+    type Children = Vec<Self>;
+
+    fn children(&self) -> Self::Children {
+        PrefixNode::roots(self.map, self.node_columns)
+    }
+
+    fn name(&self) -> String {
+        escape_key(self.prefix)
+    }
+
+    fn columns(&self) -> HashMap<String, Datum> {
+        self.node_columns.columns(&self.map.value, self.parent)
+    }
+
+    fn keys(&self) -> Option<u64> {
+        Some(self.node_columns.keys(&self.map.value))
+    }
+}
+
+/// Tree without nodes left out by [`ResultsOptions`], see [`pruned`].
+#[derive(Debug, Clone)]
+pub struct Pruned<T> {
+    node: T,
+    depth: usize,
+    max_depth: Option<usize>,
+    min_count: u64,
+}
+
+/// Leaves out nodes deeper than `options.max_depth` (top-level nodes are at depth 1) and children
+/// of nodes with fewer keys than `options.min_count`.
+pub fn pruned<T: Tree, I: IntoIterator<Item = T>>(nodes: I, options: &ResultsOptions) -> Vec<Pruned<T>> {
+    if options.max_depth == Some(0) {
+        return Vec::new();
+    }
+    nodes.into_iter().map(|node| Pruned { node, depth: 1, max_depth: options.max_depth, min_count: options.min_count }).collect()
+}
+
+impl <T: Tree> Tree for Pruned<T> {
+    type Children = Vec<Self>;
+
+    fn children(&self) -> Self::Children {
+        if self.max_depth.is_some_and(|max_depth| self.depth >= max_depth) || self.node.keys().is_some_and(|keys| keys < self.min_count) {
+            return Vec::new();
+        }
+        self.node.children().into_iter().map(|node| Pruned { node, depth: self.depth + 1, ..*self }).collect()
+    }
+
+    fn name(&self) -> String {
+        self.node.name()
+    }
+
+    fn columns(&self) -> HashMap<String, Datum> {
+        self.node.columns()
+    }
+
+    fn column(&self, column: &str) -> Option<Datum> {
+        self.node.column(column)
+    }
+
+    fn keys(&self) -> Option<u64> {
+        self.node.keys()
+    }
+}
+
+/// Order of siblings, by values of a column or by names.
+#[derive(Debug, Clone, Copy)]
+pub struct SortOrder<'c> {
+    /// Column with the values, names are compared without it. Nodes without the column are the smallest.
+    pub column: Option<&'c str>,
+    pub descending: bool,
+}
+
+impl <'c> SortOrder<'c> {
+    /// Largest values first, the order of the results of the analyses.
+    pub fn descending(column: &'c str) -> Self {
+        SortOrder { column: Some(column), descending: true }
+    }
+
+    fn compare<T: Tree>(&self, a: &T, b: &T) -> Ordering {
+        let ordering = match self.column {
+            Some(column) => {
+                let value = |node: &T| node.column(column).map_or(f64::NEG_INFINITY, |datum| datum.value());
+                value(a).partial_cmp(&value(b)).unwrap_or(Ordering::Equal)
+            },
+            None => a.name().cmp(&b.name()),
+        };
+        if self.descending { ordering.reverse() } else { ordering }
+    }
+}
+
+/// Tree with siblings sorted on every level, see [`sorted`].
+#[derive(Debug, Clone)]
+pub struct Sorted<'c, T> {
+    node: T,
+    order: SortOrder<'c>,
+}
+
+/// Sorts the nodes and children of every node, the sort is stable.
+pub fn sorted<'c, T: Tree, I: IntoIterator<Item = T>>(nodes: I, order: SortOrder<'c>) -> Vec<Sorted<'c, T>> {
+    let mut nodes = nodes.into_iter().collect::<Vec<_>>();
+    nodes.sort_by(|a, b| order.compare(a, b));
+    nodes.into_iter().map(|node| Sorted { node, order }).collect()
+}
+
+impl <'c, T: Tree> Tree for Sorted<'c, T> {
+    type Children = Vec<Self>;
+
+    fn children(&self) -> Self::Children {
+        sorted(self.node.children(), self.order)
+    }
+
+    fn name(&self) -> String {
+        self.node.name()
+    }
+
+    fn columns(&self) -> HashMap<String, Datum> {
+        self.node.columns()
+    }
+
+    fn column(&self, column: &str) -> Option<Datum> {
+        self.node.column(column)
+    }
+
+    fn keys(&self) -> Option<u64> {
+        self.node.keys()
+    }
+}
+
+/// Visits the nodes depth-first, every node before its children, with its depth (top-level nodes are at 0).
+/// Children of a node are visited only if `visit` returns `true` for it.
+pub fn walk<T: Tree, I: IntoIterator<Item = T>, F: FnMut(&T, usize) -> bool>(nodes: I, visit: &mut F) {
+    fn walk_level<T: Tree, I: IntoIterator<Item = T>, F: FnMut(&T, usize) -> bool>(nodes: I, depth: usize, visit: &mut F) {
+        for node in nodes {
+            if visit(&node, depth) {
+                walk_level(node.children(), depth + 1, visit);
+            }
+        }
+    }
+    walk_level(nodes, 0, visit);
+}
+
+impl Results {
+    /// Results of trees of prefixes, top-level items are the children of the root of `map` (keyspaces).
+    /// Siblings are sorted by `order` and the tree is pruned by `options`.
+    pub fn from_prefix_map<T, C: NodeColumns<T>>(map: &PrefixMap<T>, node_columns: &C, columns: Vec<String>, order: SortOrder, options: &ResultsOptions) -> Results {
+        let nodes = pruned(sorted(PrefixNode::roots(map, node_columns), order), options);
+        Results { items: nodes.iter().map(Item::from_tree).collect(), columns }
+    }
+}
+
+impl Item {
+    /// Copies a node of any tree with all its descendants.
+    pub fn from_tree<T: Tree>(node: &T) -> Item {
+        Item {
+            name: node.name(),
+            columns: node.columns(),
+            children: node.children().into_iter().map(|child| Item::from_tree(&child)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use crate::analysis::ResultsOptions;
+    use crate::results::{Datum, Item};
+    use super::{pruned, sorted, walk, SortOrder, Tree};
+
+    fn item(name: &str, value: Option<i64>, children: Vec<Item>) -> Item {
+        let columns = value.map(|value| ("c".to_string(), Datum::Count(value))).into_iter().collect::<HashMap<_, _>>();
+        Item { name: name.to_string(), columns, children }
+    }
+
+    fn names<T: Tree>(nodes: Vec<T>) -> Vec<(usize, String)> {
+        let mut names = Vec::new();
+        walk(nodes, &mut |node: &T, depth| {
+            names.push((depth, node.name()));
+            true
+        });
+        names
+    }
+
+    #[test]
+    fn test_sorted_pruned() {
+        let items = vec![
+            item("a", Some(1), vec![item("a1", None, vec![])]),
+            item("b", Some(3), vec![item("b1", Some(1), vec![item("deep", Some(1), vec![])]), item("b2", Some(2), vec![])]),
+        ];
+        let by_value = sorted(pruned(&items, &ResultsOptions { max_depth: Some(2), ..Default::default() }), SortOrder::descending("c"));
+        assert_eq!(
+            names(by_value),
+            [(0, "b"), (1, "b2"), (1, "b1"), (0, "a"), (1, "a1")].map(|(depth, name)| (depth, name.to_string())),
+        );
+
+        let by_name = sorted(&items, SortOrder { column: None, descending: true });
+        assert_eq!(Item::from_tree(&by_name[0]).children[1].children[0].name, "deep");
+        assert_eq!(Item::from_tree(&by_name[0]).children[0].name, "b2");
+        assert!(pruned(&items, &ResultsOptions { max_depth: Some(0), ..Default::default() }).is_empty());
+    }
 }
@@ -0,0 +1,59 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::keyspace_info::KeyspaceId;
+
+/// Describes how an analysis was produced.
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub host: String,
+    pub port: u16,
+    pub started_at: SystemTime,
+    pub samples: Vec<SampleInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SampleInfo {
+    pub keyspace: KeyspaceId,
+    pub sample_size: u64,
+    pub total_keys: u64,
+    /// Sampled keys which could not be analyzed
+    pub skipped: u64,
+}
+
+/// Formats time as ISO 8601 in UTC (e.g. `2022-02-10T13:45:00Z`).
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(super::format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(super::format_timestamp(UNIX_EPOCH + Duration::from_secs(1644500700)), "2022-02-10T13:45:00Z");
+        assert_eq!(super::format_timestamp(UNIX_EPOCH + Duration::from_secs(951782400)), "2000-02-29T00:00:00Z");
+    }
+}
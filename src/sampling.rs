@@ -0,0 +1,241 @@
+use redis::RedisResult;
+use crate::error::{Error, Result, SkipReason};
+use crate::keyspace_info::KeyspaceId;
+use crate::metrics::{Collector, Memory, COLLECTORS};
+use crate::progress::Progress;
+use crate::redis::RedisConnection;
+use crate::snapshot::{KeySample, KeyspaceSample, SkippedKeys, Snapshot};
+
+/// Number of keys sampled from a keyspace with given number of keys.
+pub fn sample_size(total_keys: u64) -> u64 {
+    match total_keys {
+        0..=100 => total_keys,
+        101..=500 => total_keys / 5,
+        501..=1000 => total_keys / 10,
+        _ => 200,
+    }
+}
+
+/// Which keys are sampled and what is collected for them.
+#[derive(Debug, Clone, Default)]
+pub struct SamplingOptions {
+    /// Metrics collected for every key in addition to memory usage, which is always collected
+    pub metrics: Vec<&'static dyn Collector>,
+    /// Sample only keys matching this glob-style pattern (`SCAN MATCH`)
+    pub pattern: Option<String>,
+    /// Sample only these databases, all if empty
+    pub databases: Vec<KeyspaceId>,
+    /// Do not sample these databases
+    pub excluded_databases: Vec<KeyspaceId>,
+}
+
+impl SamplingOptions {
+    /// Adds a metric unless it is already collected.
+    pub fn with_metric(mut self, metric: &'static dyn Collector) -> Self {
+        if !self.metrics.iter().any(|m| m.name() == metric.name()) {
+            self.metrics.push(metric);
+        }
+        self
+    }
+
+    /// Collectors run for every key, memory usage first.
+    fn collectors(&self) -> Vec<&'static dyn Collector> {
+        let mut collectors = vec![&Memory as &dyn Collector];
+        // Keep the order of `COLLECTORS`, e.g. the type is read before cardinality which needs it
+        collectors.extend(COLLECTORS.iter().copied().filter(|collector| {
+            collector.name() != Memory.name() && self.metrics.iter().any(|metric| metric.name() == collector.name())
+        }));
+        collectors
+    }
+
+    fn is_sampled(&self, keyspace: KeyspaceId) -> bool {
+        (self.databases.is_empty() || self.databases.contains(&keyspace)) && !self.excluded_databases.contains(&keyspace)
+    }
+}
+
+/// Escapes characters with special meaning in glob-style patterns of Redis.
+pub fn escape_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Share of the hash table visited by `SCAN` before returning `cursor`.
+///
+/// The cursor is incremented in its reversed bits, so its reversed value grows linearly
+/// with visited buckets, independently of the size of the table.
+fn visited_share(cursor: u64) -> f64 {
+    if cursor == 0 {
+        1.0
+    } else {
+        cursor.reverse_bits() as f64 / 2f64.powi(64)
+    }
+}
+
+/// Keys found by `SCAN`.
+struct ScannedKeys {
+    keys: Vec<Vec<u8>>,
+    /// Estimated number of keys matching the pattern, `None` without a pattern
+    matching_keys: Option<f64>,
+}
+
+/// Scans until `limit` keys are found or the whole keyspace was scanned.
+fn scan_keys(connection: &mut RedisConnection, limit: u64, pattern: Option<&str>) -> RedisResult<ScannedKeys> {
+    // With a pattern most of the scanned keys may not match, so bigger steps are faster
+    let count = if pattern.is_some() { limit.max(1000) } else { limit.max(10) };
+    let mut keys = Vec::new();
+    let mut found = 0;
+    let mut cursor = 0;
+    loop {
+        let (next, page) = connection.scan(cursor, pattern, count)?;
+        found += page.len();
+        keys.extend(page);
+        cursor = next;
+        if cursor == 0 || keys.len() as u64 >= limit {
+            break;
+        }
+    }
+    keys.truncate(limit as usize);
+    Ok(ScannedKeys {
+        keys,
+        matching_keys: pattern.map(|_| found as f64 / visited_share(cursor)),
+    })
+}
+
+/// Reads metrics of one sampled key, the inner error tells why the key is skipped.
+fn sample_key(connection: &mut RedisConnection, key: Vec<u8>, collectors: &[&dyn Collector]) -> Result<std::result::Result<KeySample, SkipReason>> {
+    let mut sample = KeySample::new(key, 0);
+    for collector in collectors {
+        match collector.collect(connection, &mut sample) {
+            Ok(true) => {},
+            Ok(false) => return Ok(Err(SkipReason::Vanished)),
+            Err(e) => return SkipReason::of(&e).ok_or(Error::Redis(e)).map(Err),
+        }
+    }
+    Ok(Ok(sample))
+}
+
+/// Samples keys of all keyspaces of the server.
+///
+/// Keys deleted during sampling and keys the user cannot read are skipped and counted,
+/// any other error stops sampling. Progress is reported per keyspace with one unit per sampled key.
+pub fn sample(host: &str, port: u16, options: &SamplingOptions, progress: &dyn Progress) -> Result<Snapshot> {
+    let mut connection = RedisConnection::open(host.to_string(), port, KeyspaceId::new(0), progress)?;
+    let keyspaces = connection.keyspaces()?;
+    progress.message(&format!("Found {} keyspaces", keyspaces.keyspaces.len()));
+    let server_info = connection.info("server")?;
+
+    let mut keyspace_ids = keyspaces.keyspaces.keys()
+        .copied()
+        .filter(|keyspace| options.is_sampled(*keyspace))
+        .collect::<Vec<_>>();
+    keyspace_ids.sort_by_key(|keyspace| keyspace.as_i64());
+
+    let collectors = options.collectors();
+    let mut snapshot = Snapshot::new(host.to_string(), port, server_info, keyspaces);
+    snapshot.memory_info = Some(connection.memory_info()?);
+    // Managed services often rename or disable CONFIG and MEMORY STATS
+    snapshot.maxmemory = match connection.config_get("maxmemory") {
+        Ok(maxmemory) => maxmemory.and_then(|maxmemory| maxmemory.parse().ok()),
+        Err(e) => {
+            progress.message(&format!("CONFIG GET maxmemory failed, using maxmemory from INFO memory: {}", e));
+            None
+        }
+    };
+    snapshot.memory_stats = match connection.memory_stats() {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            progress.message(&format!("MEMORY STATS failed, memory overview is less detailed: {}", e));
+            None
+        }
+    };
+    for keyspace in keyspace_ids {
+        let keyspace_info = snapshot.keyspaces.keyspaces[&keyspace].clone();
+        let total = keyspace_info.keys;
+        let sample_size = sample_size(total);
+        progress.message(&match &options.pattern {
+            Some(pattern) => format!("Getting sample from db{keyspace} - {sample_size} keys matching '{pattern}' of {total} total"),
+            None => format!("Getting sample from db{keyspace} - {sample_size} keys of {total} total"),
+        });
+        connection.select(keyspace)?;
+        let scanned = scan_keys(&mut connection, sample_size, options.pattern.as_deref())?;
+        if let Some(matching_keys) = scanned.matching_keys {
+            progress.message(&format!("Estimated {matching_keys:.0} matching keys"));
+        }
+        let scanned_count = scanned.keys.len() as u64;
+        progress.start(Some(scanned_count));
+        let mut keys = Vec::with_capacity(scanned.keys.len());
+        let mut skipped = SkippedKeys::default();
+        for key in scanned.keys {
+            match sample_key(&mut connection, key, &collectors)? {
+                Ok(sample) => keys.push(sample),
+                Err(reason) => skipped.add(reason),
+            }
+            progress.inc(1);
+        }
+        progress.finish();
+        if !skipped.is_empty() {
+            progress.message(&skipped.to_string());
+        }
+        snapshot.samples.push(KeyspaceSample {
+            keyspace,
+            info: keyspace_info,
+            // Estimates are scaled by the number of keys which were really analyzed
+            sample_size: scanned_count.saturating_sub(skipped.total()),
+            keys,
+            skipped,
+            matching_keys: scanned.matching_keys,
+        });
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::keyspace_info::KeyspaceId;
+    use crate::metrics::{Cardinality, Frequency, ValueType};
+    use super::{escape_pattern, visited_share, SamplingOptions};
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("orders:"), "orders:");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+
+    #[test]
+    fn test_databases() {
+        let all = SamplingOptions::default();
+        assert!(all.is_sampled(KeyspaceId::new(5)));
+        let options = SamplingOptions {
+            databases: vec![KeyspaceId::new(0), KeyspaceId::new(3)],
+            excluded_databases: vec![KeyspaceId::new(3)],
+            ..Default::default()
+        };
+        assert!(options.is_sampled(KeyspaceId::new(0)));
+        assert!(!options.is_sampled(KeyspaceId::new(1)));
+        assert!(!options.is_sampled(KeyspaceId::new(3)));
+    }
+
+    #[test]
+    fn test_collectors() {
+        let names = |options: &SamplingOptions| options.collectors().iter().map(|c| c.name()).collect::<Vec<_>>();
+        assert_eq!(names(&SamplingOptions::default()), ["memory"]);
+        let options = SamplingOptions::default().with_metric(&Cardinality).with_metric(&Frequency).with_metric(&ValueType).with_metric(&Frequency);
+        assert_eq!(options.metrics.len(), 3);
+        assert_eq!(names(&options), ["memory", "type", "cardinality", "frequency"]);
+    }
+
+    #[test]
+    fn test_visited_share() {
+        assert_eq!(visited_share(0), 1.0);
+        // Table with 8 buckets: cursors go 0, 4, 2, 6, 1, ...
+        assert_eq!(visited_share(4), 1.0 / 8.0);
+        assert_eq!(visited_share(6), 3.0 / 8.0);
+        assert_eq!(visited_share(1), 4.0 / 8.0);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::error::SkipReason;
+use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+use crate::memory_info::{MemoryInfo, MemoryStats};
+use crate::prefix_map::PrefixMap;
+use crate::run_info::{RunInfo, SampleInfo};
+
+/// Version of the snapshot file format, bumped on incompatible changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Raw sampled data of one analysis run. Everything else is computed from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format_version: u32,
+    pub host: String,
+    pub port: u16,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// Fields of `INFO server`
+    pub server_info: BTreeMap<String, String>,
+    pub keyspaces: KeyspacesInfo,
+    /// `INFO memory` and `MEMORY STATS` of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_info: Option<MemoryInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_stats: Option<MemoryStats>,
+    /// `maxmemory` from `CONFIG GET`, 0 if the server has no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxmemory: Option<u64>,
+    pub samples: Vec<KeyspaceSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyspaceSample {
+    pub keyspace: KeyspaceId,
+    pub info: KeyspaceInfo,
+    /// Number of keys which were analyzed, sampled keys which were skipped are not included
+    pub sample_size: u64,
+    pub keys: Vec<KeySample>,
+    #[serde(default, skip_serializing_if = "SkippedKeys::is_empty")]
+    pub skipped: SkippedKeys,
+    /// Estimated number of keys matching the pattern the sample was restricted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matching_keys: Option<f64>,
+}
+
+/// Sampled keys which could not be analyzed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SkippedKeys {
+    /// Deleted or expired between `SCAN` and reading them
+    pub vanished: u64,
+    /// Not readable by the user because of ACL (`NOPERM`)
+    pub no_permission: u64,
+}
+
+impl SkippedKeys {
+    pub fn add(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Vanished => self.vanished += 1,
+            SkipReason::NoPermission => self.no_permission += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.vanished + self.no_permission
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+}
+
+impl Display for SkippedKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Skipped {} sampled keys: {} deleted during sampling, {} not readable because of ACL (NOPERM)",
+            self.total(), self.vanished, self.no_permission,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySample {
+    #[serde(with = "binary_key")]
+    pub key: Vec<u8>,
+    pub memory_usage: u64,
+    /// Type as returned by `TYPE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<String>,
+    /// Remaining time to live in milliseconds, -1 for keys without expiration (like `PTTL`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
+    /// Number of elements, 1 for strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elements: Option<u64>,
+    /// Logarithmic access frequency counter (`OBJECT FREQ`), available with LFU eviction policies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<u64>,
+}
+
+impl KeySample {
+    pub fn new(key: Vec<u8>, memory_usage: u64) -> Self {
+        KeySample {
+            key,
+            memory_usage,
+            value_type: None,
+            ttl: None,
+            elements: None,
+            frequency: None,
+        }
+    }
+}
+
+/// Keys are saved as strings if they are valid UTF-8 and as `{"base64": "..."}` otherwise.
+mod binary_key {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum SavedKey {
+        Text(String),
+        Binary { base64: String },
+    }
+
+    pub fn serialize<S: Serializer>(key: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(key) {
+            Ok(text) => SavedKey::Text(text.to_string()),
+            Err(_) => SavedKey::Binary { base64: STANDARD.encode(key) },
+        }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match SavedKey::deserialize(deserializer)? {
+            SavedKey::Text(text) => Ok(text.into_bytes()),
+            SavedKey::Binary { base64 } => STANDARD.decode(base64).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn new(host: String, port: u16, server_info: BTreeMap<String, String>, keyspaces: KeyspacesInfo) -> Self {
+        Snapshot {
+            format_version: FORMAT_VERSION,
+            host,
+            port,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            server_info,
+            keyspaces,
+            memory_info: None,
+            memory_stats: None,
+            maxmemory: None,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if snapshot.format_version != FORMAT_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unsupported snapshot format version {}", snapshot.format_version),
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Memory limit of the server, `INFO memory` is used when `CONFIG` is not available.
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.maxmemory.or(self.memory_info.as_ref().map(|info| info.maxmemory))
+    }
+
+    /// Skipped keys of all keyspaces.
+    pub fn skipped(&self) -> SkippedKeys {
+        let mut skipped = SkippedKeys::default();
+        for sample in &self.samples {
+            skipped.vanished += sample.skipped.vanished;
+            skipped.no_permission += sample.skipped.no_permission;
+        }
+        skipped
+    }
+
+    /// Sum of estimated memory usage of all keys.
+    pub fn estimated_memory_usage(&self) -> f64 {
+        self.samples.iter()
+            .filter(|sample| sample.sample_size > 0)
+            .map(|sample| {
+                let sampled = sample.keys.iter().map(|key| key.memory_usage).sum::<u64>() as f64;
+                sampled / sample.analyzed_share()
+            })
+            .sum()
+    }
+
+    pub fn run_info(&self) -> RunInfo {
+        RunInfo {
+            host: self.host.clone(),
+            port: self.port,
+            started_at: UNIX_EPOCH + Duration::from_secs(self.created_at),
+            samples: self.samples.iter().map(|sample| SampleInfo {
+                keyspace: sample.keyspace,
+                sample_size: sample.sample_size,
+                total_keys: sample.info.keys,
+                skipped: sample.skipped.total(),
+            }).collect(),
+        }
+    }
+}
+
+impl KeyspaceSample {
+    /// Number of keys the sample represents, only matching keys if it was restricted by a pattern.
+    pub fn population(&self) -> f64 {
+        self.matching_keys.unwrap_or(self.info.keys as f64)
+    }
+
+    /// Share of the represented keys which were analyzed, estimates are scaled by it.
+    pub fn analyzed_share(&self) -> f64 {
+        self.sample_size as f64 / self.population()
+    }
+
+    /// Sampled keys in a prefix tree.
+    pub fn prefix_map(&self) -> PrefixMap<Option<&KeySample>> {
+        let mut prefix_map = PrefixMap::default();
+        for key in &self.keys {
+            prefix_map.insert(key.key.clone(), key);
+        }
+        prefix_map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use crate::keyspace_info::{KeyspaceId, KeyspaceInfo, KeyspacesInfo};
+    use super::{KeySample, KeyspaceSample, SkippedKeys, Snapshot};
+
+    #[test]
+    fn test_save_and_load() {
+        let info = KeyspaceInfo::new(10, 1, 100);
+        let mut snapshot = Snapshot::new(
+            "localhost".to_string(),
+            6379,
+            BTreeMap::from([("redis_version".to_string(), "7.0.0".to_string())]),
+            KeyspacesInfo { keyspaces: HashMap::from([(KeyspaceId::new(3), info.clone())]) },
+        );
+        snapshot.samples.push(KeyspaceSample {
+            keyspace: KeyspaceId::new(3),
+            info,
+            sample_size: 2,
+            keys: vec![KeySample::new(b"foo:bar".to_vec(), 56), KeySample::new(b"bin:\xff".to_vec(), 60)],
+            skipped: SkippedKeys { vanished: 2, no_permission: 1 },
+            matching_keys: None,
+        });
+
+        let path = std::env::temp_dir().join(format!("redis-analyzer-snapshot-{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.keyspaces, snapshot.keyspaces);
+        assert_eq!(loaded.server_info, snapshot.server_info);
+        assert_eq!(loaded.samples[0].keys[0].key, b"foo:bar");
+        assert_eq!(loaded.samples[0].keys[1].key, b"bin:\xff");
+        let saved = serde_json::to_value(&snapshot.samples[0].keys).unwrap();
+        assert_eq!(saved[0]["key"], "foo:bar");
+        assert_eq!(saved[1]["key"]["base64"], "YmluOv8=");
+        assert_eq!(loaded.samples[0].keys[0].memory_usage, 56);
+        assert_eq!(loaded.skipped().total(), 3);
+    }
+}
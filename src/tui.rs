@@ -0,0 +1,499 @@
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use crate::prefix_map::is_prefix;
+use crate::results::{write_text, Datum, Item, Results};
+use crate::results2::{sorted, walk, SortOrder, Tree};
+
+const TOP_KEYS_COUNT: usize = 20;
+
+/// Sampled keys with their memory usage, by name of the top-level item (keyspace).
+pub type SampledKeys = HashMap<String, Vec<(String, u64)>>;
+
+/// Identifies an item by names of the items on the path from the root.
+type ItemPath = Vec<String>;
+
+struct Row<'a> {
+    path: ItemPath,
+    item: &'a Item,
+}
+
+/// Item in the table, its children are shown only when it is expanded.
+struct Visible<'a> {
+    item: &'a Item,
+    path: ItemPath,
+    expanded: &'a HashSet<ItemPath>,
+}
+
+impl <'a> Tree for Visible<'a> {
+    type Children = Vec<Self>;
+
+    fn children(&self) -> Self::Children {
+        if !self.expanded.contains(&self.path) {
+            return Vec::new();
+        }
+        self.item.children.iter().map(|item| {
+            let mut path = self.path.clone();
+            path.push(item.name.clone());
+            Visible { item, path, expanded: self.expanded }
+        }).collect()
+    }
+
+    fn name(&self) -> String {
+        let marker = match (self.item.children.is_empty(), self.expanded.contains(&self.path)) {
+            (true, _) => "  ",
+            (false, true) => "▾ ",
+            (false, false) => "▸ ",
+        };
+        format!("{}{}", marker, self.item.name)
+    }
+
+    fn columns(&self) -> HashMap<String, Datum> {
+        self.item.columns.clone()
+    }
+
+    fn column(&self, column: &str) -> Option<Datum> {
+        self.item.columns.get(column).cloned()
+    }
+}
+
+enum Mode {
+    Browse,
+    Search(String),
+    TopKeys(ItemPath),
+}
+
+struct Tui<'a> {
+    results: Results,
+    keys: &'a SampledKeys,
+    expanded: HashSet<ItemPath>,
+    selected: usize,
+    scroll: usize,
+    sort_column: Option<usize>,
+    sort_descending: bool,
+    query: String,
+    mode: Mode,
+    message: String,
+}
+
+/// Raw mode and the alternate screen, the terminal is restored when dropped, also when the UI panics.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter<W: Write>(out: &mut W) -> std::io::Result<Self> {
+        enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(out, EnterAlternateScreen, Hide)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stdout(), Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Runs interactive browser of the results until the user quits.
+pub fn run(results: &Results, keys: &SampledKeys) -> std::io::Result<()> {
+    let mut tui = Tui::new(results.clone(), keys);
+    let mut out = std::io::stdout();
+    let _guard = TerminalGuard::enter(&mut out)?;
+    tui.event_loop(&mut out)
+}
+
+impl <'a> Tui<'a> {
+    fn new(results: Results, keys: &'a SampledKeys) -> Self {
+        Tui {
+            results,
+            keys,
+            expanded: HashSet::new(),
+            selected: 0,
+            scroll: 0,
+            sort_column: None,
+            sort_descending: true,
+            query: String::new(),
+            mode: Mode::Browse,
+            message: String::new(),
+        }
+    }
+
+    fn event_loop<W: Write>(&mut self, out: &mut W) -> std::io::Result<()> {
+        loop {
+            self.draw(out)?;
+            if let Event::Key(key) = read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if !self.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut rows = Vec::new();
+        let mut path = ItemPath::new();
+        walk(&self.results.items, &mut |item: &&Item, depth| {
+            path.truncate(depth);
+            path.push(item.name.clone());
+            rows.push(Row { path: path.clone(), item });
+            self.expanded.contains(&path)
+        });
+        rows
+    }
+
+    /// Returns `false` when the application should quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        self.message.clear();
+        match &mut self.mode {
+            Mode::Search(query) => {
+                match key.code {
+                    KeyCode::Esc => self.mode = Mode::Browse,
+                    KeyCode::Enter => {
+                        self.query = query.clone();
+                        self.mode = Mode::Browse;
+                        self.find_next(false);
+                    },
+                    KeyCode::Backspace => { query.pop(); },
+                    KeyCode::Char(c) => query.push(c),
+                    _ => (),
+                }
+                return true;
+            },
+            Mode::TopKeys(_) => {
+                self.mode = Mode::Browse;
+                return !matches!(key.code, KeyCode::Char('q'));
+            },
+            Mode::Browse => (),
+        }
+
+        let rows_count = self.rows().len();
+        let page = self.page_height();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = min(self.selected + 1, rows_count.saturating_sub(1)),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected = min(self.selected + page, rows_count.saturating_sub(1)),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = rows_count.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Char(' ') | KeyCode::Enter => self.toggle(true),
+            KeyCode::Left | KeyCode::Char('h') => self.toggle(false),
+            KeyCode::Char('s') => self.next_sort_column(),
+            KeyCode::Char('r') => {
+                self.sort_descending = !self.sort_descending;
+                self.sort();
+            },
+            KeyCode::Char('/') => self.mode = Mode::Search(String::new()),
+            KeyCode::Char('n') => self.find_next(true),
+            KeyCode::Char('t') => {
+                if let Some(row) = self.rows().get(self.selected) {
+                    self.mode = Mode::TopKeys(row.path.clone());
+                }
+            },
+            _ => (),
+        }
+        true
+    }
+
+    /// Expands (or collapses) selected item. Collapsing an already collapsed item selects its parent.
+    fn toggle(&mut self, expand: bool) {
+        let rows = self.rows();
+        let row = match rows.get(self.selected) {
+            Some(row) => row,
+            None => return,
+        };
+        let path = row.path.clone();
+        let has_children = !row.item.children.is_empty();
+        let is_expanded = self.expanded.contains(&path);
+        if expand && has_children {
+            if is_expanded {
+                self.expanded.remove(&path);
+            } else {
+                self.expanded.insert(path);
+            }
+        } else if !expand && is_expanded {
+            self.expanded.remove(&path);
+        } else if !expand && path.len() > 1 {
+            let parent = &path[..path.len() - 1];
+            self.selected = rows.iter().position(|r| r.path == parent).unwrap_or(self.selected);
+        }
+    }
+
+    fn next_sort_column(&mut self) {
+        self.sort_column = match self.sort_column {
+            None if !self.results.columns.is_empty() => Some(0),
+            Some(index) if index + 1 < self.results.columns.len() => Some(index + 1),
+            _ => None,
+        };
+        self.sort_descending = self.sort_column.is_some();
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        let selected_path = self.rows().get(self.selected).map(|row| row.path.clone());
+        let column = self.sort_column.map(|index| self.results.columns[index].clone());
+        let order = SortOrder { column: column.as_deref(), descending: self.sort_descending };
+        self.results.items = sorted(&self.results.items, order).iter().map(Item::from_tree).collect();
+        if let Some(path) = selected_path {
+            self.selected = self.rows().iter().position(|row| row.path == path).unwrap_or(0);
+        }
+    }
+
+    /// Selects next item whose name contains the query, expanding its ancestors.
+    fn find_next(&mut self, skip_current: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+        let mut found = Vec::new();
+        let mut path = ItemPath::new();
+        walk(&self.results.items, &mut |item: &&Item, depth| {
+            path.truncate(depth);
+            path.push(item.name.clone());
+            if item.name.contains(&self.query) {
+                found.push(path.clone());
+            }
+            true
+        });
+        if found.is_empty() {
+            self.message = format!("No prefix matches '{}'", self.query);
+            return;
+        }
+
+        let current = self.rows().get(self.selected).map(|row| row.path.clone());
+        let start = current
+            .and_then(|current| found.iter().position(|path| *path == current))
+            .map_or(0, |index| if skip_current { index + 1 } else { index });
+        let target = found[start % found.len()].clone();
+        for length in 1..target.len() {
+            self.expanded.insert(target[..length].to_vec());
+        }
+        self.selected = self.rows().iter().position(|row| row.path == target).unwrap_or(0);
+        self.message = format!("{} prefixes match '{}' (n for next)", found.len(), self.query);
+    }
+
+    /// Returns the item at given path.
+    fn item(&self, path: &[String]) -> Option<&Item> {
+        let (first, rest) = path.split_first()?;
+        let mut item = self.results.items.iter().find(|item| item.name == *first)?;
+        for name in rest {
+            item = item.children.iter().find(|child| child.name == *name)?;
+        }
+        Some(item)
+    }
+
+    /// Returns sampled keys of given item sorted by memory usage.
+    ///
+    /// A leaf which is a whole key matches only the same key, other items match all keys they are a prefix of.
+    fn top_keys(&self, path: &[String]) -> Vec<(&str, u64)> {
+        let keys = match path.first().and_then(|keyspace| self.keys.get(keyspace)) {
+            Some(keys) => keys,
+            None => return vec![],
+        };
+        let prefix = if path.len() > 1 { path[path.len() - 1].as_str() } else { "" };
+        let is_key = path.len() > 1
+            && !is_prefix(prefix.as_bytes())
+            && self.item(path).is_none_or(|item| item.children.is_empty());
+        let mut top = keys.iter()
+            .filter(|(key, _)| if is_key { key == prefix } else { key.starts_with(prefix) })
+            .map(|(key, memory_usage)| (key.as_str(), *memory_usage))
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        top.truncate(TOP_KEYS_COUNT);
+        top
+    }
+
+    fn page_height(&self) -> usize {
+        let (_, height) = size().unwrap_or((80, 24));
+        max(height as usize, 3) - 2
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> std::io::Result<()> {
+        let width = size()?.0 as usize;
+        // Rows of the page below the header, the status line is the last one
+        let page = self.page_height();
+        let lines = match &self.mode {
+            Mode::TopKeys(path) => self.top_keys_lines(path),
+            _ => {
+                if self.selected < self.scroll {
+                    self.scroll = self.selected;
+                } else if self.selected >= self.scroll + page {
+                    self.scroll = self.selected + 1 - page;
+                }
+                self.table_lines(self.scroll, page)
+            },
+        };
+
+        queue!(out, Clear(ClearType::All))?;
+        for (y, (line, highlighted)) in lines.into_iter().take(page + 1).enumerate() {
+            queue!(out, MoveTo(0, y as u16))?;
+            if highlighted {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(out, Print(truncate(&line, width)), SetAttribute(Attribute::Reset))?;
+        }
+        let status = match &self.mode {
+            Mode::Search(query) => format!("/{}", query),
+            Mode::TopKeys(_) => "Press any key to return".to_string(),
+            Mode::Browse if !self.message.is_empty() => self.message.clone(),
+            Mode::Browse => format!(
+                "q quit | ←/→ collapse/expand | s sort ({}{}) | r reverse | / search | n next | t top keys",
+                self.sort_column.map_or("prefix", |index| &self.results.columns[index]),
+                if self.sort_descending { " ↓" } else { " ↑" },
+            ),
+        };
+        queue!(
+            out,
+            MoveTo(0, (page + 1) as u16),
+            SetAttribute(Attribute::Bold),
+            Print(truncate(&status, width)),
+            SetAttribute(Attribute::Reset),
+        )?;
+        out.flush()
+    }
+
+    /// Lines of the table with a flag whether the line is highlighted.
+    fn table_lines(&self, scroll: usize, page: usize) -> Vec<(String, bool)> {
+        let visible = self.results.items.iter()
+            .map(|item| Visible { item, path: vec![item.name.clone()], expanded: &self.expanded })
+            .collect::<Vec<_>>();
+        let mut table = String::new();
+        write_text(visible, &self.results.columns, &mut table).unwrap();
+        let mut lines = table.lines();
+        let header = lines.next().unwrap_or_default().to_string();
+        let mut table_lines = vec![(header, false)];
+        table_lines.extend(lines.enumerate().skip(scroll).take(page).map(|(index, line)| (line.to_string(), index == self.selected)));
+        table_lines
+    }
+
+    fn top_keys_lines(&self, path: &[String]) -> Vec<(String, bool)> {
+        let mut lines = vec![(format!("Top sampled keys of {}", path.join(" › ")), true)];
+        let top = self.top_keys(path);
+        if top.is_empty() {
+            lines.push(("No sampled keys".to_string(), false));
+        }
+        let width = top.iter().map(|(_, memory_usage)| memory_usage.to_string().len()).max().unwrap_or(0);
+        for (key, memory_usage) in top {
+            lines.push((format!("{:>width$}  {}", memory_usage, key, width = width), false));
+        }
+        lines
+    }
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use crate::results::{Datum, Item, Results};
+    use super::{SampledKeys, Tui};
+
+    fn item(name: &str, count: i64, children: Vec<Item>) -> Item {
+        Item {
+            name: name.to_string(),
+            columns: {
+                let mut map = HashMap::new();
+                map.insert("count".to_string(), Datum::Count(count));
+                map
+            },
+            children,
+        }
+    }
+
+    fn results() -> Results {
+        Results {
+            columns: vec!["count".to_string()],
+            items: vec![
+                item("0", 3, vec![
+                    item("foo:", 1, vec![]),
+                    item("bar:", 2, vec![item("bar:baz:", 2, vec![])]),
+                ]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_expand_and_sort() {
+        let keys = SampledKeys::new();
+        let mut tui = Tui::new(results(), &keys);
+        assert_eq!(tui.rows().len(), 1);
+
+        tui.toggle(true);
+        let names = tui.rows().iter().map(|row| row.item.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["0", "foo:", "bar:"]);
+
+        tui.next_sort_column();
+        let names = tui.rows().iter().map(|row| row.item.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["0", "bar:", "foo:"]);
+    }
+
+    #[test]
+    fn test_table_lines() {
+        let keys = SampledKeys::new();
+        let mut tui = Tui::new(results(), &keys);
+        tui.toggle(true);
+        tui.selected = 2;
+        assert_eq!(tui.table_lines(1, 10), vec![
+            ("         | count".to_string(), false),
+            ("    foo: |     1".to_string(), false),
+            ("  ▸ bar: |     2".to_string(), true),
+        ]);
+    }
+
+    #[test]
+    fn test_search_expands_ancestors() {
+        let keys = SampledKeys::new();
+        let mut tui = Tui::new(results(), &keys);
+        tui.query = "baz".to_string();
+        tui.find_next(false);
+        let rows = tui.rows();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[tui.selected].item.name, "bar:baz:");
+    }
+
+    #[test]
+    fn test_top_keys() {
+        let mut keys = SampledKeys::new();
+        keys.insert("0".to_string(), vec![
+            ("bar:baz:1".to_string(), 10),
+            ("bar:baz:2".to_string(), 30),
+            ("foo:1".to_string(), 50),
+        ]);
+        let tui = Tui::new(results(), &keys);
+        let path = vec!["0".to_string(), "bar:".to_string()];
+        assert_eq!(tui.top_keys(&path), vec![("bar:baz:2", 30), ("bar:baz:1", 10)]);
+        assert_eq!(tui.top_keys(&path[..1]).len(), 3);
+    }
+
+    #[test]
+    fn test_top_keys_of_leaf() {
+        let mut keys = SampledKeys::new();
+        keys.insert("0".to_string(), vec![
+            ("foo:1".to_string(), 10),
+            ("foo:10".to_string(), 30),
+            ("foo:100".to_string(), 20),
+        ]);
+        let results = Results {
+            columns: vec!["count".to_string()],
+            items: vec![item("0", 3, vec![item("foo:", 3, vec![item("foo:1", 1, vec![]), item("foo:10", 1, vec![])])])],
+        };
+        let tui = Tui::new(results, &keys);
+        let path = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(tui.top_keys(&path(&["0", "foo:", "foo:1"])), vec![("foo:1", 10)]);
+        assert_eq!(tui.top_keys(&path(&["0", "foo:", "foo:10"])), vec![("foo:10", 30)]);
+        assert_eq!(tui.top_keys(&path(&["0", "foo:"])).len(), 3);
+    }
+}
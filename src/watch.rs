@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use crate::results::{Datum, Item, Results};
+use crate::snapshot::Snapshot;
+
+/// Number of runs kept in the history.
+const HISTORY_LENGTH: usize = 60;
+
+/// Identifies an item by names of the items on the path from the root.
+pub type ItemPath = Vec<String>;
+
+/// Estimates of one prefix in one run.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Estimate {
+    pub count: f64,
+    pub memory_usage: f64,
+}
+
+/// Estimates of all prefixes from one run.
+#[derive(Debug, Clone)]
+pub struct Run {
+    /// Unix timestamp in seconds
+    pub time: u64,
+    pub estimates: HashMap<ItemPath, Estimate>,
+    /// `used_memory` of the server, if known
+    pub used_memory: Option<u64>,
+}
+
+impl Run {
+    pub fn new(time: u64, results: &Results) -> Run {
+        fn add_items(estimates: &mut HashMap<ItemPath, Estimate>, path: &mut ItemPath, items: &[Item]) {
+            for item in items {
+                path.push(item.name.clone());
+                let column = |name: &str| item.columns.get(name).map_or(0.0, Datum::value);
+                estimates.insert(path.clone(), Estimate {
+                    count: column("estimated_total_count"),
+                    memory_usage: column("estimated_total_memory_usage"),
+                });
+                add_items(estimates, path, &item.children);
+                path.pop();
+            }
+        }
+
+        let mut estimates = HashMap::new();
+        add_items(&mut estimates, &mut Vec::new(), &results.items);
+        Run { time, estimates, used_memory: None }
+    }
+
+    /// Run of results built from a snapshot, with `used_memory` of the server when the snapshot was taken.
+    pub fn from_snapshot(snapshot: &Snapshot, results: &Results) -> Run {
+        Run {
+            used_memory: snapshot.memory_info.as_ref().map(|info| info.used_memory),
+            ..Run::new(snapshot.created_at, results)
+        }
+    }
+
+    pub fn estimate(&self, path: &ItemPath) -> Estimate {
+        self.estimates.get(path).copied().unwrap_or_default()
+    }
+}
+
+/// Rolling history of estimates of repeated runs, the oldest runs are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    runs: Vec<Run>,
+}
+
+impl History {
+    pub fn add(&mut self, run: Run) {
+        if self.runs.len() == HISTORY_LENGTH {
+            self.runs.remove(0);
+        }
+        self.runs.push(run);
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    /// Adds changes since the previous run and growth per hour over the whole history to the results
+    /// of the latest run. Prefixes missing in a run are counted as empty.
+    pub fn add_trend_columns(&self, results: &mut Results) {
+        fn add_items(history: &History, path: &mut ItemPath, items: &mut [Item]) {
+            let (oldest, previous, latest) = (&history.runs[0], &history.runs[history.runs.len() - 2], &history.runs[history.runs.len() - 1]);
+            let hours = latest.time.saturating_sub(oldest.time) as f64 / 3600.0;
+            for item in items {
+                path.push(item.name.clone());
+                let current = latest.estimate(path);
+                let before = previous.estimate(path);
+                item.columns.insert("count_delta".to_string(), Datum::Stat(current.count - before.count));
+                item.columns.insert("memory_usage_delta".to_string(), Datum::Stat(current.memory_usage - before.memory_usage));
+                if hours > 0.0 {
+                    let growth = (current.memory_usage - oldest.estimate(path).memory_usage) / hours;
+                    item.columns.insert("memory_growth_per_hour".to_string(), Datum::Stat(growth));
+                }
+                add_items(history, path, &mut item.children);
+                path.pop();
+            }
+        }
+
+        if self.runs.len() < 2 {
+            return;
+        }
+        results.columns.extend(["count_delta", "memory_usage_delta", "memory_growth_per_hour"].map(str::to_string));
+        add_items(self, &mut Vec::new(), &mut results.items);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use crate::results::{Datum, Item, Results};
+    use super::{History, Run};
+
+    fn results(memory_usage: f64) -> Results {
+        let item = |name: &str, memory_usage: f64, children| Item {
+            name: name.to_string(),
+            columns: HashMap::from([
+                ("estimated_total_count".to_string(), Datum::Stat(10.0)),
+                ("estimated_total_memory_usage".to_string(), Datum::Stat(memory_usage)),
+            ]),
+            children,
+        };
+        Results {
+            columns: vec!["estimated_total_count".to_string(), "estimated_total_memory_usage".to_string()],
+            items: vec![item("0", memory_usage + 100.0, vec![item("foo:", memory_usage, vec![])])],
+        }
+    }
+
+    #[test]
+    fn test_trend() {
+        let mut history = History::default();
+        let mut latest = results(1000.0);
+        history.add(Run::new(0, &results(500.0)));
+        history.add_trend_columns(&mut latest);
+        assert!(!latest.columns.contains(&"count_delta".to_string()));
+
+        history.add(Run::new(1800, &results(800.0)));
+        history.add(Run::new(3600, &latest));
+        history.add_trend_columns(&mut latest);
+        let foo = &latest.items[0].children[0];
+        assert!(matches!(foo.columns["memory_usage_delta"], Datum::Stat(d) if d == 200.0));
+        assert!(matches!(foo.columns["count_delta"], Datum::Stat(d) if d == 0.0));
+        assert!(matches!(foo.columns["memory_growth_per_hour"], Datum::Stat(g) if g == 500.0));
+    }
+
+    #[test]
+    fn test_history_length() {
+        let mut history = History::default();
+        for time in 0..100 {
+            history.add(Run::new(time, &results(0.0)));
+        }
+        assert_eq!(history.runs().len(), super::HISTORY_LENGTH);
+        assert_eq!(history.runs()[0].time, 40);
+    }
+}